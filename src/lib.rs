@@ -30,33 +30,656 @@
 //! }
 //! # }
 //! ```
-use actix_http::{Request, Response};
+//!
+//! # Multiple headers of the same name
+//!
+//! Responses include every header in both `headers` and `multiValueHeaders`
+//! of a Lambda response. API Gateway REST APIs and ALB target groups with
+//! multi-value headers enabled use the latter, so repeated headers such as
+//! the `Set-Cookie` headers added by `HttpResponse::Ok().cookie(...)` are
+//! delivered as they are. For HTTP APIs with payload format version 2.0,
+//! `Set-Cookie` headers are sent in the `cookies` field instead, and the values
+//! of other repeated headers are joined with `, ` into a single value.
+//!
+//! `headers` can hold only one value per header name. Therefore, ALB target
+//! groups without multi-value headers only receive one of them. See
+//! [`LambdaHttpServer::alb_cookie_case_hack`] for a workaround for cookies.
+use actix_http::{Extensions, Request, RequestHead, Response};
 use actix_server_config::ServerConfig;
 use actix_service::{IntoNewService, NewService, Service};
 use actix_web::{
-    dev::{MessageBody, ResponseBody},
-    http::uri,
+    body::Body,
+    dev::{MessageBody, Payload, ResponseBody, ServiceRequest, ServiceResponse},
+    error::{
+        ErrorInternalServerError, ErrorNotFound, JsonPayloadError, PathError, PayloadError,
+        QueryPayloadError, UrlencodedError,
+    },
+    http::{header::HttpDate, uri, Error as HttpError, HeaderName, HeaderValue, StatusCode},
     web::{Bytes, BytesMut},
-    Error,
+    Error, FromRequest, HttpRequest,
 };
 use futures::Stream;
-use lambda_http::{http::header::CONTENT_TYPE, Body as LambdaBody, RequestExt};
+use lambda_http::{
+    http::{
+        header::{
+            ACCEPT, ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS,
+            ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_HEADERS,
+            ACCESS_CONTROL_REQUEST_METHOD, AUTHORIZATION, CONNECTION, CONTENT_ENCODING,
+            CONTENT_LENGTH, CONTENT_TYPE, COOKIE, DATE, ETAG, EXPECT, FORWARDED, HOST,
+            IF_NONE_MATCH, LOCATION, ORIGIN, SET_COOKIE, TRANSFER_ENCODING, VARY,
+        },
+        Method,
+    },
+    request::RequestContext,
+    Body as LambdaBody, RequestExt, StrMap,
+};
 use lambda_runtime::error::HandlerError;
-use log::{debug, warn};
-use percent_encoding::utf8_percent_encode;
-use std::{fmt::Write, marker::PhantomData, mem::replace};
+use percent_encoding::{percent_decode, utf8_percent_encode, PATH_SEGMENT_ENCODE_SET};
+use serde::de::DeserializeOwned;
+use std::{
+    collections::HashMap,
+    fmt::{self, Write},
+    marker::PhantomData,
+    mem::{replace, take},
+    net::{IpAddr, SocketAddr},
+    time::{Duration, SystemTime},
+};
+use tokio::timer::Timeout;
+
+/// Wrappers of `log::debug!` and `log::warn!` that prepend the prefix of the
+/// event being processed (see `LambdaHttpServer::log_prefix`).
+macro_rules! debug {
+    ($($arg:tt)+) => {
+        log::debug!("{}{}", crate::log_context::Prefix, format_args!($($arg)+))
+    };
+}
+
+macro_rules! warn {
+    ($($arg:tt)+) => {
+        log::warn!("{}{}", crate::log_context::Prefix, format_args!($($arg)+))
+    };
+}
+
+mod request;
+mod response;
+
+mod log_context {
+    use std::{cell::RefCell, fmt};
+
+    thread_local! {
+        static PREFIX: RefCell<String> = const { RefCell::new(String::new()) };
+    }
+
+    /// Set the prefix of log messages emitted by the current thread while
+    /// processing the invocation `ctx` (see `LambdaHttpServer::log_prefix`
+    /// and `LambdaHttpServer::log_request_id`).
+    pub fn set_prefix(prefix: &str, request_id: bool, ctx: &lambda_runtime::Context) {
+        PREFIX.with(|x| {
+            let mut x = x.borrow_mut();
+            x.clear();
+            x.push_str(prefix);
+            if request_id {
+                x.push('[');
+                x.push_str(&ctx.aws_request_id);
+                x.push_str("] ");
+            }
+        });
+    }
+
+    /// Displays the prefix of log messages emitted by the current thread.
+    pub struct Prefix;
+
+    impl fmt::Display for Prefix {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            PREFIX.with(|x| f.write_str(&x.borrow()))
+        }
+    }
+}
 
 /// `percent_encoding` implements the percent encoding algorithm in the WHATWG
 /// URL standard which is designed to deal with input that may already be
 /// partially percent-encoded. To do a full percent encoding, we add `%` to the
-/// encode set.
+/// encode set. `+` is also added because actix-web decodes it as a space.
 mod enc_set {
-    use percent_encoding::{define_encode_set, QUERY_ENCODE_SET};
+    use percent_encoding::{define_encode_set, EncodeSet, QUERY_ENCODE_SET};
     define_encode_set! {
-        pub URL_ENCODE = [QUERY_ENCODE_SET] | {'%'}
+        pub URL_ENCODE = [QUERY_ENCODE_SET] | {'%', '+'}
+    }
+    define_encode_set! {
+        /// `&` and `=` would let a key or value of a query parameter
+        /// introduce another parameter.
+        pub STRICT_ENCODE = [URL_ENCODE] | {'&', '='}
+    }
+
+    /// Encodes everything but unreserved characters.
+    #[derive(Clone, Copy)]
+    #[allow(non_camel_case_types)]
+    pub struct FULL_ENCODE;
+
+    impl EncodeSet for FULL_ENCODE {
+        fn contains(&self, byte: u8) -> bool {
+            !(byte.is_ascii_alphanumeric() || b"-._~".contains(&byte))
+        }
+    }
+
+    /// Encodes non-ASCII characters, which `http::Uri` rejects.
+    #[derive(Clone, Copy)]
+    #[allow(non_camel_case_types)]
+    pub struct NON_ASCII_ENCODE;
+
+    impl EncodeSet for NON_ASCII_ENCODE {
+        fn contains(&self, byte: u8) -> bool {
+            !byte.is_ascii()
+        }
+    }
+}
+
+/// A request extension containing the path prefix removed by API Gateway, such
+/// as a base path mapping of a custom domain name or a stage name.
+///
+/// See [`LambdaHttpServer::detect_base_path`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasePath(pub String);
+
+/// A request extension containing the cookies sent with the request.
+///
+/// The cookies are taken from the `Cookie` header or, for HTTP API (payload
+/// format version 2.0), the `cookies` field of the event.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Cookies(Vec<(String, String)>);
+
+impl Cookies {
+    fn from_headers<'a>(values: impl Iterator<Item = &'a HeaderValue>) -> Self {
+        let pairs = (values.filter_map(|value| value.to_str().ok()))
+            .flat_map(|value| value.split(';'))
+            .filter_map(|pair| {
+                let mut parts = pair.splitn(2, '=');
+                let name = parts.next()?.trim();
+                let value = parts.next()?.trim();
+                if name.is_empty() {
+                    None
+                } else {
+                    Some((name.to_owned(), value.to_owned()))
+                }
+            })
+            .collect();
+        Cookies(pairs)
+    }
+
+    /// Get the value of the first cookie with the specified name.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        (self.0.iter())
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Iterate over the names and values of the cookies.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        (self.0.iter()).map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+}
+
+/// A request extension containing the original method of a request whose
+/// method was overridden by `X-HTTP-Method-Override`.
+///
+/// See [`LambdaHttpServer::honor_method_override`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OriginalMethod(pub Method);
+
+/// A request extension containing the `Expect` header of a request, which is
+/// removed by the connector because the request body is already available in
+/// full.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OriginalExpect(pub HeaderValue);
+
+/// A request extension indicating the form of the body in the event.
+///
+/// The application receives the same payload regardless of the form; a
+/// base64-encoded body is decoded by the connector, and its headers (e.g.,
+/// `Content-Type`) are left intact, so extractors such as `web::Json` behave
+/// identically in both cases.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventBody {
+    /// The event had no body (`body` was `null` or missing). The request has
+    /// no `Content-Length` header in this case.
+    Absent,
+    /// The event had a text body, which may be empty.
+    Text,
+    /// The event had a base64-encoded body (`isBase64Encoded`).
+    Binary,
+}
+
+/// A request extension containing the path parameters extracted by API
+/// Gateway.
+#[derive(Debug, Clone)]
+pub struct PathParameters(pub StrMap);
+
+/// A request extension containing the fields of a form body
+/// (`application/x-www-form-urlencoded`) in order.
+///
+/// See [`LambdaHttpServer::parse_form_body`].
+#[derive(Debug, Clone, Default)]
+pub struct FormParams(pub Vec<(String, String)>);
+
+impl FormParams {
+    /// Get the value of the first field with the specified name.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        (self.0.iter())
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// A request extension containing the context returned by an API Gateway
+/// Lambda authorizer (`requestContext.authorizer`), such as the claims of a
+/// verified token.
+///
+/// This is inserted only if the event includes a non-empty authorizer
+/// context.
+#[derive(Debug, Clone)]
+pub struct AuthorizerContext(pub HashMap<String, serde_json::Value>);
+
+/// A request extension containing the ID of the request.
+///
+/// This is the API Gateway request ID (`requestContext.requestId`) if
+/// available, or the Lambda request ID otherwise. If neither is available
+/// (e.g., when the function is invoked directly or by
+/// `LambdaHttpServer::run_local`), a random UUID is generated if the `uuid`
+/// feature is enabled, and this extension is absent otherwise.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+/// An extractor that deserializes the path parameters extracted by API Gateway
+/// (see [`PathParameters`]), similarly to `actix_web::web::Path`.
+///
+/// # Examples
+///
+/// ```
+/// use actix_lambda_http::LambdaPath;
+/// use serde::Deserialize;
+///
+/// #[derive(Deserialize)]
+/// struct Info {
+///     id: u32,
+/// }
+///
+/// // API Gateway resource path: /users/{id}
+/// fn index(info: LambdaPath<Info>) -> String {
+///     format!("user {}", info.id)
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LambdaPath<T>(pub T);
+
+impl<T> LambdaPath<T> {
+    /// Deconstruct to an inner value.
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> std::ops::Deref for LambdaPath<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> std::ops::DerefMut for LambdaPath<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T> FromRequest for LambdaPath<T>
+where
+    T: DeserializeOwned,
+{
+    type Error = Error;
+    type Future = Result<Self, Error>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        // Go through `application/x-www-form-urlencoded` so that non-string
+        // fields are parsed in the same way as `web::Query`
+        let params: Vec<(String, String)> = (req.extensions().get::<PathParameters>())
+            .map(|params| {
+                (params.0.iter())
+                    .map(|(key, value)| (key.to_owned(), value.to_owned()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let encoded = serde_urlencoded::to_string(params).map_err(ErrorInternalServerError)?;
+
+        serde_urlencoded::from_str(&encoded)
+            .map(LambdaPath)
+            .map_err(|e| {
+                debug!("Failed to deserialize the path parameters: {:?}", e);
+                ErrorNotFound(e)
+            })
+    }
+}
+
+/// The configuration of the built-in CORS preflight responder.
+///
+/// See [`LambdaHttpServer::cors_preflight`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CorsPreflight {
+    /// The allowed origins (e.g., `https://example.com`). `*` allows any
+    /// origin.
+    pub allowed_origins: Vec<String>,
+    /// The allowed methods (e.g., `PUT`).
+    pub allowed_methods: Vec<String>,
+    /// The allowed request headers. `*` allows any headers.
+    pub allowed_headers: Vec<String>,
+    /// The value of `Access-Control-Max-Age` in seconds.
+    pub max_age: Option<u32>,
+}
+
+impl CorsPreflight {
+    /// Construct a response to a preflight request. Returns `None` if the
+    /// request is not a preflight request.
+    fn respond(&self, headers: &lambda_http::http::HeaderMap) -> Option<Response<Bytes>> {
+        let method = headers.get(ACCESS_CONTROL_REQUEST_METHOD)?;
+        let origin = (headers.get(ORIGIN))
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("");
+
+        let allow_origin = if self.allowed_origins.iter().any(|x| x == "*") {
+            "*"
+        } else if self.allowed_origins.iter().any(|x| x == origin) {
+            origin
+        } else {
+            debug!("The origin {:?} is not allowed, returning 403", origin);
+            return Some(status_response(StatusCode::FORBIDDEN));
+        };
+
+        if !(self.allowed_methods.iter()).any(|x| x.as_bytes() == method.as_bytes()) {
+            debug!("The method {:?} is not allowed, returning 403", method);
+            return Some(status_response(StatusCode::FORBIDDEN));
+        }
+
+        let allow_headers = if self.allowed_headers.iter().any(|x| x == "*") {
+            (headers.get(ACCESS_CONTROL_REQUEST_HEADERS))
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or("")
+                .to_owned()
+        } else {
+            self.allowed_headers.join(", ")
+        };
+
+        let mut resp = status_response(StatusCode::NO_CONTENT);
+        let resp_headers = resp.headers_mut();
+        let mut insert = |name, value: &str| {
+            if let Ok(value) = HeaderValue::from_str(value) {
+                resp_headers.insert(name, value);
+            }
+        };
+        insert(ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+        insert(
+            ACCESS_CONTROL_ALLOW_METHODS,
+            &self.allowed_methods.join(", "),
+        );
+        if !allow_headers.is_empty() {
+            insert(ACCESS_CONTROL_ALLOW_HEADERS, &allow_headers);
+        }
+        if let Some(max_age) = self.max_age {
+            insert(ACCESS_CONTROL_MAX_AGE, &max_age.to_string());
+        }
+        insert(VARY, "Origin");
+        Some(resp)
+    }
+}
+
+/// A request extension indicating the kind of the event source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventSource {
+    /// API Gateway REST API.
+    ApiGatewayRest,
+    /// API Gateway HTTP API.
+    ApiGatewayHttp,
+    /// Application Load Balancer.
+    Alb,
+}
+
+/// A response extension to override the fields of a Lambda response.
+///
+/// # Examples
+///
+/// ```
+/// use actix_lambda_http::LambdaResponseHints;
+/// use actix_web::HttpResponse;
+///
+/// let mut resp = HttpResponse::Ok().body("hello");
+/// resp.extensions_mut().insert(LambdaResponseHints {
+///     status_description: Some("200 Fine".to_owned()),
+///     ..Default::default()
+/// });
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LambdaResponseHints {
+    /// The value of `statusDescription`. This is only used for responses to
+    /// ALB because API Gateway rejects a response including one. If `None`,
+    /// it's derived from the status code.
+    pub status_description: Option<String>,
+    /// Specifies whether the body should be base64-encoded
+    /// (`isBase64Encoded`). If `None`, it's decided by
+    /// [`LambdaHttpServer::binary_media_type_fn`]. An empty body is never
+    /// base64-encoded.
+    pub binary: Option<bool>,
+}
+
+/// The error type returned by [`LambdaHttpServer::try_binary_media_types`]
+/// when a media type is malformed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidMediaType(pub String);
+
+impl fmt::Display for InvalidMediaType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid media type: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidMediaType {}
+
+/// The top-level media types registered by IANA.
+const TOP_LEVEL_MEDIA_TYPES: &[&str] = &[
+    "application",
+    "audio",
+    "example",
+    "font",
+    "image",
+    "message",
+    "model",
+    "multipart",
+    "text",
+    "video",
+];
+
+/// Specifies how query parameters are percent-encoded when reconstructing a
+/// request URI.
+#[derive(Default)]
+pub enum QueryEncoding {
+    /// Encode control characters, non-ASCII characters, space, `"`, `#`, `<`,
+    /// `>`, `%`, and `+`. Note that `&` and `=` are not encoded, so a key or
+    /// value containing them is seen as multiple parameters by the
+    /// application; use `Strict` to prevent this.
+    ///
+    /// This is the default value.
+    #[default]
+    Standard,
+    /// Encode the same characters as `Standard` as well as `&` and `=`, which
+    /// would otherwise change the meaning of the query string.
+    Strict,
+    /// Encode all characters but unreserved characters (alphanumeric
+    /// characters, `-`, `.`, `_`, and `~`). Spaces are encoded as `%20`.
+    Full,
+    /// Same as `Full` except that spaces are encoded as `+`, as done by
+    /// `application/x-www-form-urlencoded`.
+    Form,
+    /// Use the given function to encode a key or value.
+    Custom(Box<dyn FnMut(&str) -> String>),
+}
+
+impl QueryEncoding {
+    fn encode(&mut self, input: &str, out: &mut String) {
+        match self {
+            QueryEncoding::Standard => {
+                write!(out, "{}", utf8_percent_encode(input, enc_set::URL_ENCODE)).unwrap()
+            }
+            QueryEncoding::Strict => write!(
+                out,
+                "{}",
+                utf8_percent_encode(input, enc_set::STRICT_ENCODE)
+            )
+            .unwrap(),
+            QueryEncoding::Full => {
+                write!(out, "{}", utf8_percent_encode(input, enc_set::FULL_ENCODE)).unwrap()
+            }
+            QueryEncoding::Form => {
+                for chunk in utf8_percent_encode(input, enc_set::FULL_ENCODE) {
+                    // `%` is always encoded, so `%20` can only originate from a space
+                    out.push_str(if chunk == "%20" { "+" } else { chunk });
+                }
+            }
+            QueryEncoding::Custom(f) => out.push_str(&f(input)),
+        }
+    }
+}
+
+impl fmt::Debug for QueryEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QueryEncoding::Standard => f.write_str("Standard"),
+            QueryEncoding::Strict => f.write_str("Strict"),
+            QueryEncoding::Full => f.write_str("Full"),
+            QueryEncoding::Form => f.write_str("Form"),
+            QueryEncoding::Custom(_) => f.write_str("Custom(..)"),
+        }
     }
 }
 
+/// Specifies how a query parameter with multiple values is serialized when
+/// reconstructing a request URI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum QueryArrayStyle {
+    /// Repeat the key for each value, e.g., `key=a&key=b`.
+    ///
+    /// This is the default value.
+    #[default]
+    Repeat,
+    /// Repeat the key with `[]` appended for each value, e.g.,
+    /// `key[]=a&key[]=b`.
+    Brackets,
+    /// Join the values with commas, e.g., `key=a,b`. Commas in the values
+    /// are encoded only if `QueryEncoding` encodes them.
+    Comma,
+}
+
+/// Specifies how a trailing slash in the request path is handled when
+/// reconstructing a request URI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum TrailingSlash {
+    /// Leave the path as it is.
+    ///
+    /// This is the default value.
+    #[default]
+    Keep,
+    /// Remove trailing slashes. The root path `/` is left as it is.
+    Trim,
+    /// Append a slash if the path doesn't end with one.
+    Append,
+}
+
+type UriTransformFn =
+    Box<dyn FnMut(uri::Uri, &lambda_http::Request) -> Result<uri::Uri, HttpError>>;
+
+type OnInitFn<T> = Box<dyn FnOnce(&T)>;
+
+type BodyErrorFallbackFn = Box<dyn FnMut(&Error) -> Response>;
+
+type MapResponseFn = Box<dyn FnMut(&mut Response<Bytes>)>;
+
+type MapRequestBodyFn = Box<dyn FnMut(&RequestHead, Bytes) -> Result<Bytes, Error>>;
+
+type MapLambdaRequestFn =
+    Box<dyn FnMut(lambda_http::Request, &lambda_runtime::Context) -> lambda_http::Request>;
+
+type InsertExtensionFn = Box<dyn Fn(&mut Extensions)>;
+
+type VerifyBodyFn = Box<dyn FnMut(&RequestHead, &[u8]) -> Result<(), Response<Bytes>>>;
+
+type HeaderFilterFn = Box<dyn FnMut(&HeaderName, &HeaderValue) -> bool>;
+
+type EventHandler<'a> = dyn FnMut(
+        serde_json::Value,
+        lambda_runtime::Context,
+    ) -> Result<response::LambdaResponse, HandlerError>
+    + 'a;
+
+type RespondFn<'a> = dyn FnMut(
+        Result<lambda_http::Request, Response<Bytes>>,
+        lambda_runtime::Context,
+    ) -> Result<lambda_http::Response<LambdaBody>, HandlerError>
+    + 'a;
+
+/// The service type of an `actix_web::App` accepted by [`LambdaHttpServer`].
+///
+/// actix-web doesn't export a name for this type, which makes it hard to
+/// write the return type of a function building an `App`. Use
+/// `App<impl AppService, Body>` instead (see [`LambdaHttpServer::new`]).
+pub trait AppService<B = Body>:
+    NewService<
+    Config = (),
+    Request = ServiceRequest,
+    Response = ServiceResponse<B>,
+    Error = Error,
+    InitError = (),
+>
+{
+}
+
+impl<T, B> AppService<B> for T where
+    T: NewService<
+        Config = (),
+        Request = ServiceRequest,
+        Response = ServiceResponse<B>,
+        Error = Error,
+        InitError = (),
+    >
+{
+}
+
+/// The error type of [`LambdaHttpServer::start`].
+#[derive(Debug)]
+pub enum StartError<E> {
+    /// The application service couldn't be constructed.
+    Init(E),
+    /// The Tokio runtime used to communicate with the Lambda runtime API
+    /// couldn't be created (see [`LambdaHttpServer::lean_runtime`]).
+    Runtime(std::io::Error),
+}
+
+/// The error type of [`LambdaHttpServer::run_local`].
+#[cfg(feature = "local")]
+#[derive(Debug)]
+pub enum LocalError<E> {
+    /// The application service couldn't be constructed.
+    Init(E),
+    /// The event couldn't be read, or the response couldn't be written.
+    Json(serde_json::Error),
+    /// The event couldn't be processed.
+    Handler(HandlerError),
+}
+
+/// A connector that serves an actix-web application on AWS Lambda.
+///
+/// The type parameters follow those of `actix_web::HttpServer`: `F` is the
+/// application factory (usually a closure returning `App`), `S` is the service
+/// created from the factory's result, and `B` is the response body type. There
+/// are no requirements on `S::InitError`; it's only returned from
+/// [`start`](Self::start).
 pub struct LambdaHttpServer<F, R, S, B>
 where
     F: FnOnce() -> R,
@@ -64,11 +687,76 @@ where
     S: NewService<Config = ServerConfig, Request = Request>,
     S::Error: Into<Error>,
     S::Response: Into<Response<B>>,
-    S::Service: 'static,
-    B: MessageBody + 'static,
+    B: MessageBody,
 {
     factory: F,
+    call_factory_mut: Option<fn(&mut F) -> R>,
     binary_media_type_fn: Box<dyn FnMut(&str) -> bool>,
+    host_header_authority: bool,
+    sync_host_header: bool,
+    base_path: Option<String>,
+    pass_through_unmounted_paths: bool,
+    default_authority: String,
+    default_scheme: uri::Scheme,
+    max_request_body_bytes: Option<usize>,
+    max_uri_length: Option<usize>,
+    max_headers: Option<usize>,
+    max_header_bytes: Option<usize>,
+    normalize_invalid_responses: bool,
+    use_raw_path: bool,
+    query_encoding: QueryEncoding,
+    query_array_style: QueryArrayStyle,
+    trailing_slash: TrailingSlash,
+    strip_stage: bool,
+    rewrite_redirect_locations: bool,
+    lean_runtime: bool,
+    detect_base_path: bool,
+    uri_transform: Option<UriTransformFn>,
+    conditional_get: bool,
+    path_from_proxy_parameter: bool,
+    merge_slashes: bool,
+    on_init: Option<OnInitFn<S::Service>>,
+    allowed_hosts: Option<Vec<String>>,
+    honor_method_override: bool,
+    propagate_trace_id: bool,
+    echo_trace_id: bool,
+    init_retries: usize,
+    alb_cookie_case_hack: bool,
+    kept_hop_by_hop_headers: Vec<String>,
+    synthesize_forwarded_headers: bool,
+    cors_preflight: Option<CorsPreflight>,
+    default_content_type: Option<HeaderValue>,
+    header_name_case: Vec<String>,
+    redacted_headers: Vec<HeaderName>,
+    redacted_query_parameters: Vec<String>,
+    log_prefix: String,
+    log_request_id: bool,
+    encode_invalid_header_values: bool,
+    response_header_filter: HeaderFilterFn,
+    json_error_bodies: bool,
+    date_header: bool,
+    body_read_timeout: Option<Duration>,
+    append_charset: bool,
+    binary_accept_types: Vec<String>,
+    peer_addr_from_source_ip: bool,
+    body_error_fallback: Option<BodyErrorFallbackFn>,
+    set_content_length: bool,
+    map_response: Option<MapResponseFn>,
+    default_response_headers: Vec<(HeaderName, HeaderValue)>,
+    synthesize_rfc7239_forwarded: bool,
+    health_check: Option<(String, StatusCode)>,
+    decompress_request_body: bool,
+    parse_form_body: bool,
+    payload_chunk_size: Option<usize>,
+    error_content_type: Option<HeaderValue>,
+    error_body_template: Option<String>,
+    verify_body: Option<VerifyBodyFn>,
+    map_request_body: Option<MapRequestBodyFn>,
+    strip_response_header_prefix: bool,
+    stripped_response_header_prefix: String,
+    map_lambda_request: Option<MapLambdaRequestFn>,
+    utf8_binary_as_text: Option<usize>,
+    request_extensions: Vec<InsertExtensionFn>,
     _t: PhantomData<(S, B)>,
 }
 
@@ -79,14 +767,106 @@ where
     S: NewService<Config = ServerConfig, Request = Request>,
     S::Error: Into<Error>,
     S::Response: Into<Response<B>>,
-    S::Service: 'static,
-    B: MessageBody + 'static,
+    B: MessageBody,
 {
     /// Construct a `LambdaHttpServer`.
+    ///
+    /// `app_factory` is usually a closure returning `App`, in which case all
+    /// type parameters are inferred. To build the application in a separate
+    /// function, use [`AppService`] in its return type:
+    ///
+    /// ```
+    /// use actix_lambda_http::AppService;
+    /// use actix_web::{body::Body, web, App};
+    ///
+    /// fn app() -> App<impl AppService, Body> {
+    ///     App::new().route("/", web::get().to(|| "hello"))
+    /// }
+    ///
+    /// # fn main() {
+    /// fn main() {
+    ///     actix_lambda_http::LambdaHttpServer::new(app).start().unwrap();
+    /// }
+    /// # }
+    /// ```
     pub fn new(app_factory: F) -> Self {
         Self {
             factory: app_factory,
+            call_factory_mut: None,
             binary_media_type_fn: Box::new(|_| false),
+            host_header_authority: true,
+            sync_host_header: true,
+            base_path: None,
+            pass_through_unmounted_paths: false,
+            default_authority: "localhost".to_owned(),
+            default_scheme: uri::Scheme::HTTPS,
+            max_request_body_bytes: None,
+            max_uri_length: None,
+            max_headers: None,
+            max_header_bytes: None,
+            normalize_invalid_responses: false,
+            use_raw_path: false,
+            query_encoding: QueryEncoding::default(),
+            query_array_style: QueryArrayStyle::default(),
+            trailing_slash: TrailingSlash::default(),
+            strip_stage: false,
+            rewrite_redirect_locations: false,
+            lean_runtime: true,
+            detect_base_path: false,
+            uri_transform: None,
+            conditional_get: false,
+            path_from_proxy_parameter: false,
+            merge_slashes: false,
+            on_init: None,
+            allowed_hosts: None,
+            honor_method_override: false,
+            propagate_trace_id: false,
+            echo_trace_id: false,
+            init_retries: 0,
+            alb_cookie_case_hack: false,
+            kept_hop_by_hop_headers: Vec::new(),
+            synthesize_forwarded_headers: false,
+            cors_preflight: None,
+            default_content_type: None,
+            header_name_case: Vec::new(),
+            redacted_headers: vec![
+                AUTHORIZATION,
+                COOKIE,
+                SET_COOKIE,
+                HeaderName::from_static("x-api-key"),
+            ],
+            redacted_query_parameters: ["access_token", "api_key", "password", "token"]
+                .iter()
+                .map(|&name| name.to_owned())
+                .collect(),
+            log_prefix: String::new(),
+            log_request_id: false,
+            encode_invalid_header_values: false,
+            response_header_filter: Box::new(|_, _| true),
+            json_error_bodies: false,
+            date_header: true,
+            body_read_timeout: None,
+            append_charset: false,
+            binary_accept_types: Vec::new(),
+            peer_addr_from_source_ip: false,
+            body_error_fallback: None,
+            set_content_length: false,
+            map_response: None,
+            default_response_headers: Vec::new(),
+            synthesize_rfc7239_forwarded: false,
+            health_check: None,
+            decompress_request_body: false,
+            parse_form_body: false,
+            payload_chunk_size: None,
+            error_content_type: None,
+            error_body_template: None,
+            verify_body: None,
+            map_request_body: None,
+            strip_response_header_prefix: false,
+            stripped_response_header_prefix: "x-amzn-".to_owned(),
+            map_lambda_request: None,
+            utf8_binary_as_text: None,
+            request_extensions: Vec::new(),
             _t: PhantomData,
         }
     }
@@ -110,6 +890,18 @@ where
         }
     }
 
+    /// Add a function that determines whether a response payload should be
+    /// transmitted as binary. Unlike `binary_media_type_fn`, this doesn't
+    /// replace the existing function; a response payload is transmitted as
+    /// binary if either of them returns `true`.
+    pub fn add_binary_media_type_fn(self, mut value: impl FnMut(&str) -> bool + 'static) -> Self {
+        let mut existing = self.binary_media_type_fn;
+        Self {
+            binary_media_type_fn: Box::new(move |ty| existing(ty) || value(ty)),
+            ..self
+        }
+    }
+
     /// Set a set of content types transmitted as a binary response payload.
     ///
     /// This method is a wrapper for `binary_media_type_fn`.
@@ -121,171 +913,2846 @@ where
         }
     }
 
-    /// Start polling for API gateway and ALB events.
-    ///
-    /// # Panics
+    /// Set a set of media type patterns transmitted as a binary response
+    /// payload, like API Gateway's binary media types. `*` can be used as the
+    /// type (`*/*`), the subtype (`image/*`), or the part of the subtype
+    /// before a suffix (`application/*+protobuf`). Parameters of the content
+    /// type are ignored.
     ///
-    /// See [`lambda_http::start`].
-    pub fn start(self) -> Result<(), S::InitError> {
-        // TODO: Check possible causes of `new` failure
-        let mut rt = actix_rt::Runtime::new().unwrap();
+    /// This method is a wrapper for `binary_media_type_fn`.
+    pub fn binary_media_types_glob(self, value: impl IntoIterator<Item = impl AsRef<str>>) -> Self {
+        let patterns: Vec<MediaTypePattern> = (value.into_iter())
+            .map(|pattern| MediaTypePattern::new(pattern.as_ref()))
+            .collect();
+        Self {
+            binary_media_type_fn: Box::new(move |ty| {
+                let essence = ty.split(';').next().unwrap_or("").trim();
+                patterns.iter().any(|pattern| pattern.matches(essence))
+            }),
+            ..self
+        }
+    }
 
-        let cfg = ServerConfig::new("127.0.0.1:8080".parse().unwrap());
-        let new_service = (self.factory)().into_new_service();
-        let mut service = rt.block_on(new_service.new_service(&cfg))?;
+    /// Set a set of media types that cause a response to be transmitted as
+    /// binary if the first media range in the request's `Accept` header
+    /// matches one of them, like API Gateway's `CONVERT_TO_BINARY` handling.
+    /// Wildcards such as `image/*` and `*/*` are supported.
+    ///
+    /// This is checked in addition to `binary_media_type_fn`. By default, the
+    /// `Accept` header is not considered.
+    pub fn binary_by_accept(self, value: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            binary_accept_types: value.into_iter().map(Into::into).collect(),
+            ..self
+        }
+    }
 
-        let mut binary_media_type_fn = self.binary_media_type_fn;
+    /// Set the maximum size of a response payload that is transmitted as text
+    /// if it's valid UTF-8, even if `binary_media_type_fn` returns `true` for
+    /// its content type. This avoids inflating textual payloads by base64
+    /// encoding when a content type is mistakenly considered binary.
+    ///
+    /// This doesn't apply to responses transmitted as binary because of
+    /// `binary_by_accept` or `LambdaResponseHints::binary`. By default,
+    /// `binary_media_type_fn` is always followed.
+    pub fn utf8_binary_as_text(self, max_len: usize) -> Self {
+        Self {
+            utf8_binary_as_text: Some(max_len),
+            ..self
+        }
+    }
 
-        // The handler is `FnMut` (doesn't have to be `Fn + 'static`)
-        let lambda_http_handler =
-            |mut req: lambda_http::Request,
-             _ctx: lambda_runtime::Context|
-             -> Result<lambda_http::Response<LambdaBody>, HandlerError> {
-                // Construct `actix_http::Payload`
-                let mut payload = actix_http::h1::Payload::empty();
-                match req.body_mut() {
-                    LambdaBody::Empty => {}
-                    LambdaBody::Text(text) => {
-                        payload.unread_data(replace(text, String::new()).into())
-                    }
-                    LambdaBody::Binary(bytes) => {
-                        payload.unread_data(replace(bytes, Vec::new()).into())
-                    }
-                }
-
-                let mut actix_req: Request = Request::with_payload(payload.into());
-
-                // Set the headers
-                let actix_req_head = actix_req.head_mut();
-                actix_req_head.method = req.method().clone();
-                actix_req_head.version = req.version();
-                actix_req_head.headers = replace(req.headers_mut(), Default::default()).into();
-                actix_req_head.uri = {
-                    let mut builder = uri::Builder::new();
-                    builder.scheme(req.uri().scheme_part().unwrap().clone());
-                    builder.authority(req.uri().authority_part().unwrap().clone());
-
-                    // Reconstruct the encoded query parameters
-                    let query_params = req.query_string_parameters();
-                    let mut path = req.uri().path().to_string();
-                    for (i, (key, value)) in query_params.iter().enumerate() {
-                        write!(
-                            path,
-                            "{}{}={}",
-                            if i == 0 { "?" } else { "&" },
-                            utf8_percent_encode(key, enc_set::URL_ENCODE),
-                            utf8_percent_encode(value, enc_set::URL_ENCODE),
-                        )
-                        .unwrap();
-                    }
-                    builder.path_and_query(path.as_str());
+    /// Same as `binary_media_types`, but checks that each entry is a
+    /// well-formed media type with a registered top-level type.
+    pub fn try_binary_media_types(
+        self,
+        value: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Result<Self, InvalidMediaType> {
+        let types: Vec<String> = value.into_iter().map(Into::into).collect();
+        for ty in types.iter() {
+            let mime: mime::Mime = ty.parse().map_err(|_| InvalidMediaType(ty.clone()))?;
+            // `mime` accepts an empty subtype (e.g., `image/`)
+            if !TOP_LEVEL_MEDIA_TYPES.contains(&mime.type_().as_str())
+                || mime.subtype().as_str().is_empty()
+            {
+                return Err(InvalidMediaType(ty.clone()));
+            }
+        }
+        Ok(self.binary_media_types(types))
+    }
 
-                    debug!(
-                        "Original URI = {:?}, query string parameters = {:?}",
-                        req.uri(),
-                        query_params
-                    );
+    /// Set a flag indicating whether the authority of the reconstructed request
+    /// URI should be taken from the `Host` header.
+    ///
+    /// When this is enabled and the request has a `Host` header that is a
+    /// valid authority, the header takes precedence over the authority of the
+    /// event URI. Otherwise, the authority of the event URI is used.
+    ///
+    /// `lambda_http` builds the URI of a request converted from an event
+    /// from the same `Host` header, so the two differ only for requests
+    /// passed to [`LambdaHttpService`] directly (see `run_service`) or
+    /// modified by `map_lambda_request`.
+    ///
+    /// The default value is `true`.
+    pub fn host_header_authority(self, value: bool) -> Self {
+        Self {
+            host_header_authority: value,
+            ..self
+        }
+    }
 
-                    builder.build().unwrap()
-                };
+    /// Set a flag indicating whether the `Host` header should be set to the
+    /// authority of the reconstructed request URI if they differ (e.g., the
+    /// request has no `Host` header, see `default_authority`), so that
+    /// `guard::Host` and `ConnectionInfo::host` agree with the request URI.
+    ///
+    /// The default value is `true`.
+    pub fn sync_host_header(self, value: bool) -> Self {
+        Self {
+            sync_host_header: value,
+            ..self
+        }
+    }
 
-                debug!("Reconstructed URI = {:?}", actix_req_head.uri);
+    /// Set a base path under which the application is mounted.
+    ///
+    /// The base path is removed from the request path before the request is
+    /// dispatched to the application. For example, if the base path is `/api`,
+    /// a request for `/api/users` is routed as `/users`. A request for the
+    /// base path itself is routed as `/`.
+    ///
+    /// Requests whose path does not start with the base path are responded
+    /// with `404 Not Found` without invoking the application unless
+    /// `pass_through_unmounted_paths` is set.
+    pub fn mount_base_path(self, value: impl Into<String>) -> Self {
+        let mut base_path = value.into();
+        if !base_path.starts_with('/') {
+            base_path.insert(0, '/');
+        }
+        while base_path.ends_with('/') {
+            base_path.pop();
+        }
+        Self {
+            base_path: if base_path.is_empty() {
+                None
+            } else {
+                Some(base_path)
+            },
+            ..self
+        }
+    }
 
-                // TODO: Extensions from `lambda_http::RequestExt`. There are five:
-                //  - `path_parameters`
-                //  - `stage_variables`
-                //  - `request_context`
+    /// Set a flag indicating whether the base path specified by
+    /// `mount_base_path` should be prepended to the `Location` header of
+    /// responses.
+    ///
+    /// This only affects `Location` headers containing an absolute path (e.g.,
+    /// `/login`) and requests whose path was under the base path. Absolute URLs
+    /// are left intact.
+    ///
+    /// The default value is `false`.
+    pub fn rewrite_redirect_locations(self, value: bool) -> Self {
+        Self {
+            rewrite_redirect_locations: value,
+            ..self
+        }
+    }
 
-                // Call the inner handler
-                let user_resp = rt.block_on(service.call(actix_req));
+    /// Set a flag indicating whether requests whose path does not start with
+    /// the base path specified by `mount_base_path` should be dispatched to
+    /// the application as-is.
+    ///
+    /// The default value is `false`.
+    pub fn pass_through_unmounted_paths(self, value: bool) -> Self {
+        Self {
+            pass_through_unmounted_paths: value,
+            ..self
+        }
+    }
 
-                let mut actix_resp: Response<Bytes> = user_resp
-                    // Convert `S::Error` to `Error`
-                    .map_err(Into::into)
-                    // Synchronously evaluate the response body
-                    .and_then(|success_user_resp| {
-                        let mut actix_resp = success_user_resp.into();
+    /// Set the authority used to reconstruct the request URI when the event
+    /// doesn't have a `Host` header. This can happen with ALB when a client
+    /// (e.g., an HTTP/1.0 client or a health checker) doesn't send one.
+    ///
+    /// The default value is `localhost`.
+    pub fn default_authority(self, value: impl Into<String>) -> Self {
+        Self {
+            default_authority: value.into(),
+            ..self
+        }
+    }
 
-                        let resp_bytes =
-                            read_body(&mut rt, actix_resp.take_body()).map(BytesMut::freeze);
+    /// Set the scheme of the reconstructed request URI used when the event
+    /// doesn't have an `X-Forwarded-Proto` header. This affects
+    /// `ConnectionInfo::scheme` and URLs generated by `HttpRequest::url_for`.
+    ///
+    /// Unless this is `http`, the application is also configured as being
+    /// served over a secure connection (`AppConfig::secure`).
+    ///
+    /// The default value is `https`.
+    pub fn default_scheme(self, value: uri::Scheme) -> Self {
+        Self {
+            default_scheme: value,
+            ..self
+        }
+    }
 
-                        match resp_bytes {
-                            Ok(resp_bytes) => Ok(actix_resp.set_body(resp_bytes)),
-                            Err(e) => {
-                                debug!("Extracing the response failed, treating it as a handler error");
-                                Err(e)
-                            },
-                        }
-                    })
-                    // Construct a response for internal errors (if any)
-                    .unwrap_or_else(|actix_err| {
-                        debug!("Got a handler error ({:?}), generating an error response", actix_err);
+    /// Set the maximum size of a request body in bytes.
+    ///
+    /// Requests with a larger body are responded with `413 Payload Too Large`
+    /// without invoking the application. Since the whole request body is
+    /// included in an event, this check is done before the body is copied to
+    /// a payload stream. The size of a base64-encoded body is measured after
+    /// decoding, but it's calculated from the length of the encoded string, so
+    /// an oversized body in an event is rejected without being decoded. The
+    /// response can be modified by `map_response`.
+    ///
+    /// By default, the request body size is not limited.
+    pub fn max_request_body_bytes(self, value: usize) -> Self {
+        Self {
+            max_request_body_bytes: Some(value),
+            ..self
+        }
+    }
 
-                        let mut actix_resp2 = actix_err.as_response_error().render_response();
+    /// Set the maximum length of the path and query of a reconstructed request
+    /// URI in bytes. The length is measured after percent-encoding.
+    ///
+    /// Requests with a longer URI are responded with `414 URI Too Long`
+    /// without invoking the application.
+    ///
+    /// By default, the URI length is not limited.
+    pub fn max_uri_length(self, value: usize) -> Self {
+        Self {
+            max_uri_length: Some(value),
+            ..self
+        }
+    }
 
-                        // Convert the body to `Bytes` from `Body`. However, this
-                        // operation is fallible. Should this fail, return an empty body,
-                        // ignoring the error.
-                        let resp_bytes = read_body(&mut rt, actix_resp2.take_body())
-                            .map(BytesMut::freeze)
-                            .unwrap_or_else(|e| {
-                                warn!("Failed to extract the body of the error response, ignoring: {:?}", e);
+    /// Set the maximum number of request headers. Each value of a header with
+    /// multiple values is counted separately.
+    ///
+    /// Requests with more headers are responded with `431 Request Header
+    /// Fields Too Large` without invoking the application.
+    ///
+    /// By default, the number of headers is not limited.
+    pub fn max_headers(self, value: usize) -> Self {
+        Self {
+            max_headers: Some(value),
+            ..self
+        }
+    }
 
-                                Default::default()
-                            });
+    /// Set the maximum total size of request headers in bytes, which is the
+    /// sum of the lengths of the names and values of all headers (including
+    /// every value of a header with multiple values).
+    ///
+    /// Requests with larger headers are responded with `431 Request Header
+    /// Fields Too Large` without invoking the application.
+    ///
+    /// By default, the size of headers is not limited.
+    pub fn max_header_bytes(self, value: usize) -> Self {
+        Self {
+            max_header_bytes: Some(value),
+            ..self
+        }
+    }
 
-                        actix_resp2.set_body(resp_bytes)
-                    });
+    /// Set a flag indicating whether responses that are not valid as a final
+    /// HTTP response should be corrected before they are returned.
+    ///
+    /// A warning is logged regardless of this setting when the application
+    /// returns a response with an informational (`1xx`) status code or a
+    /// `204 No Content` or `304 Not Modified` response with a non-empty body,
+    /// as API Gateway may reject such a response. If this flag is set, the
+    /// body of the latter is removed. Responses of the former kind are
+    /// returned as-is because there is no sensible way to correct them.
+    ///
+    /// The default value is `false`.
+    pub fn normalize_invalid_responses(self, value: bool) -> Self {
+        Self {
+            normalize_invalid_responses: value,
+            ..self
+        }
+    }
 
-                // Construct `lambda_http::Response`.
-                let resp_body_bytes = match actix_resp.take_body() {
-                    ResponseBody::Body(bytes) => bytes,
-                    ResponseBody::Other(_) => unreachable!(),
-                };
+    /// Set a flag indicating whether the request URI should be reconstructed
+    /// from the original percent-encoded path.
+    ///
+    /// API Gateway provides a percent-decoded path, so a request for
+    /// `/files/a%2Fb` is routed as `/files/a/b` by default. If this flag is
+    /// set and the event includes the original path (`rawPath` or
+    /// `requestContext.path`), the original path is used instead, so that
+    /// Actix's own percent-decoding yields the intended path segments. Leading
+    /// segments that are not a part of the decoded path (e.g., a stage name)
+    /// are removed from the original path.
+    ///
+    /// The default value is `false`.
+    pub fn use_raw_path(self, value: bool) -> Self {
+        Self {
+            use_raw_path: value,
+            ..self
+        }
+    }
 
-                // Clone the payload as a `Vec`
-                // (I couldn't find a copy-less way to do this)
-                let resp_body_vec = resp_body_bytes.to_vec();
+    /// Set how query parameters are percent-encoded when reconstructing a
+    /// request URI.
+    ///
+    /// The default value is `QueryEncoding::Standard`.
+    pub fn query_encoding(self, value: QueryEncoding) -> Self {
+        Self {
+            query_encoding: value,
+            ..self
+        }
+    }
 
-                let content_type = (actix_resp.head().headers())
-                    .get(CONTENT_TYPE)
+    /// Set how a query parameter with multiple values (which is available
+    /// from `multiValueQueryStringParameters`) is serialized when
+    /// reconstructing a request URI. Parameters with a single value are always
+    /// serialized as `key=value`.
+    ///
+    /// The default value is `QueryArrayStyle::Repeat`.
+    pub fn query_array_style(self, value: QueryArrayStyle) -> Self {
+        Self {
+            query_array_style: value,
+            ..self
+        }
+    }
+
+    /// Set how a trailing slash in the request path is handled when
+    /// reconstructing a request URI. Unlike actix's `NormalizePath`, this also
+    /// applies to the original path (see `use_raw_path`).
+    ///
+    /// The default value is `TrailingSlash::Keep`.
+    pub fn trailing_slash(self, value: TrailingSlash) -> Self {
+        Self {
+            trailing_slash: value,
+            ..self
+        }
+    }
+
+    /// Set a flag indicating whether the path prefix removed by API Gateway
+    /// should be detected and exposed to the application as a [`BasePath`]
+    /// request extension.
+    ///
+    /// When a custom domain name and a base path mapping are in use,
+    /// `requestContext.path` includes the base path (e.g.,
+    /// `/mapping/resource`) whereas `path` doesn't (`/resource`). Similarly,
+    /// when the default endpoint is in use, the former includes the stage
+    /// name. The prefix is calculated as the difference between the two and
+    /// is useful for generating absolute URLs in the application. Requests
+    /// without a prefix don't get the extension.
+    ///
+    /// If `rewrite_redirect_locations` is also set, the detected base path is
+    /// prepended to `Location` headers unless `mount_base_path` is in effect.
+    ///
+    /// The default value is `false`.
+    pub fn detect_base_path(self, value: bool) -> Self {
+        Self {
+            detect_base_path: value,
+            ..self
+        }
+    }
+
+    /// Set a flag indicating whether the request path should be reconstructed
+    /// from the resource path and the `proxy` path parameter.
+    ///
+    /// If this flag is set and the request was routed through an API Gateway
+    /// proxy resource (e.g., `/api/{proxy+}`), the path is reconstructed by
+    /// substituting the path parameters in the resource path, percent-encoding
+    /// each path segment of the parameter values. This is useful when the
+    /// `path` field of the event does not match what the client requested. If
+    /// this option is effective, `use_raw_path` is ignored.
+    ///
+    /// The default value is `false`.
+    pub fn path_from_proxy_parameter(self, value: bool) -> Self {
+        Self {
+            path_from_proxy_parameter: value,
+            ..self
+        }
+    }
+
+    /// Set a flag indicating whether consecutive slashes in the request path
+    /// should be merged into one (e.g., `//users//42` → `/users/42`).
+    ///
+    /// Percent-encoded slashes (`%2F`) preserved by `use_raw_path` are not
+    /// affected.
+    ///
+    /// The default value is `false`.
+    pub fn merge_slashes(self, value: bool) -> Self {
+        Self {
+            merge_slashes: value,
+            ..self
+        }
+    }
+
+    /// Set a flag indicating whether the API Gateway stage name should be
+    /// removed from the request path.
+    ///
+    /// If this flag is set and the request path starts with the stage name
+    /// (`requestContext.stage`) as its first segment, the segment is removed
+    /// before the request is dispatched. For example, a request for
+    /// `/prod/users/42` on the stage `prod` is routed as `/users/42`. Paths
+    /// that don't start with the stage name (e.g., when a custom domain name
+    /// and a base path mapping are in use) and the `$default` stage of HTTP
+    /// APIs are left intact.
+    ///
+    /// The default value is `false`.
+    pub fn strip_stage(self, value: bool) -> Self {
+        Self {
+            strip_stage: value,
+            ..self
+        }
+    }
+
+    /// Set a flag indicating whether the Tokio runtime used to communicate with
+    /// the Lambda runtime API should be configured to use as few threads as
+    /// possible.
+    ///
+    /// A Lambda execution environment processes one event at a time, and the
+    /// application itself runs on a single-threaded Actix runtime regardless of
+    /// this setting. By default, Tokio creates as many worker threads as there
+    /// are CPU cores, each of which reserves a stack and per-thread
+    /// allocations that are not needed for this workload and count towards the
+    /// function's memory limit. If this flag is set, the runtime is created
+    /// with a single worker thread and a single blocking thread instead.
+    ///
+    /// This only affects [`start`](Self::start). `run_local` and
+    /// [`run_service`](Self::run_service) don't communicate with the Lambda
+    /// runtime API.
+    ///
+    /// The default value is `true`.
+    pub fn lean_runtime(self, value: bool) -> Self {
+        Self {
+            lean_runtime: value,
+            ..self
+        }
+    }
+
+    /// Set a function to rewrite the reconstructed request URI before the
+    /// request is dispatched to the application.
+    ///
+    /// The function receives the URI reconstructed by the default logic
+    /// (including the effects of the other options) and the original request.
+    /// If the function returns an error, the request is responded with
+    /// `400 Bad Request` without invoking the application.
+    pub fn uri_transform(
+        self,
+        value: impl FnMut(uri::Uri, &lambda_http::Request) -> Result<uri::Uri, HttpError> + 'static,
+    ) -> Self {
+        Self {
+            uri_transform: Some(Box::new(value)),
+            ..self
+        }
+    }
+
+    /// Set a flag indicating whether conditional `GET` and `HEAD` requests
+    /// should be handled by the connector.
+    ///
+    /// If this flag is set and a successful (`2xx`) response has an `ETag`
+    /// header matching the request's `If-None-Match` header (using the weak
+    /// comparison), the response is replaced with `304 Not Modified` with an
+    /// empty body. This is useful for applications that set `ETag` but don't
+    /// implement conditional requests by themselves.
+    ///
+    /// The default value is `false`.
+    pub fn conditional_get(self, value: bool) -> Self {
+        Self {
+            conditional_get: value,
+            ..self
+        }
+    }
+
+    /// Set a function to be called once after the application service is
+    /// constructed and before the first event is processed. This is useful
+    /// for one-time initialization such as warming up a connection pool.
+    pub fn on_init(self, value: impl FnOnce(&S::Service) + 'static) -> Self {
+        Self {
+            on_init: Some(Box::new(value)),
+            ..self
+        }
+    }
+
+    /// Set a list of host names the application accepts requests for. Each
+    /// entry is either an exact host name or a wildcard pattern like
+    /// `*.example.com`, which matches any subdomain of `example.com`.
+    /// Matching is case-insensitive and ignores the port.
+    ///
+    /// Requests with a `Host` header (or the default authority, see
+    /// [`default_authority`](Self::default_authority)) matching none of the
+    /// entries are responded with `421 Misdirected Request` without invoking
+    /// the application. This is useful for rejecting requests made through
+    /// the default `execute-api` endpoint when a custom domain name is used.
+    ///
+    /// By default, requests for all hosts are accepted.
+    pub fn allowed_hosts(self, value: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            allowed_hosts: Some(value.into_iter().map(Into::into).collect()),
+            ..self
+        }
+    }
+
+    /// Set a flag indicating whether the `X-HTTP-Method-Override` header of a
+    /// `POST` request should be honored.
+    ///
+    /// If this flag is set, the method of a `POST` request with a valid
+    /// `X-HTTP-Method-Override` header is replaced with the header's value
+    /// before the request is dispatched to the application. The original
+    /// method is available as an [`OriginalMethod`] request extension.
+    ///
+    /// The default value is `false`.
+    pub fn honor_method_override(self, value: bool) -> Self {
+        Self {
+            honor_method_override: value,
+            ..self
+        }
+    }
+
+    /// Set a flag indicating whether the `X-Amzn-Trace-Id` header should be
+    /// added to a request that doesn't have one so that tracing middleware
+    /// can continue the AWS X-Ray trace.
+    ///
+    /// The value is taken from the invocation context or, if it's
+    /// unavailable, the `_X_AMZN_TRACE_ID` environment variable.
+    ///
+    /// The default value is `false`.
+    pub fn propagate_trace_id(self, value: bool) -> Self {
+        Self {
+            propagate_trace_id: value,
+            ..self
+        }
+    }
+
+    /// Set a flag indicating whether the `X-Amzn-Trace-Id` header of a
+    /// request (including one added by `propagate_trace_id`) should be copied
+    /// to the response if the response doesn't have one.
+    ///
+    /// The default value is `false`.
+    pub fn echo_trace_id(self, value: bool) -> Self {
+        Self {
+            echo_trace_id: value,
+            ..self
+        }
+    }
+
+    /// Set a flag indicating whether multiple `Set-Cookie` headers should be
+    /// sent under different cases of the header name (`set-cookie`,
+    /// `Set-cookie`, `sEt-cookie`, ...) for ALB target groups without
+    /// multi-value headers, which would otherwise keep only one of them.
+    ///
+    /// This is only applied to events without `multiValueHeaders`. Up to 512
+    /// cookies are supported, and the rest are dropped.
+    ///
+    /// The default value is `false`.
+    pub fn alb_cookie_case_hack(self, value: bool) -> Self {
+        Self {
+            alb_cookie_case_hack: value,
+            ..self
+        }
+    }
+
+    /// Set a list of hop-by-hop headers to keep in responses.
+    ///
+    /// Hop-by-hop headers (`Connection`, `Keep-Alive`, `Proxy-Authenticate`,
+    /// `Proxy-Authorization`, `TE`, `Trailer`, `Transfer-Encoding`,
+    /// `Upgrade`, and headers listed in `Connection`) are meaningless in a
+    /// Lambda response and are removed by default.
+    pub fn keep_hop_by_hop_headers(
+        self,
+        value: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        Self {
+            kept_hop_by_hop_headers: (value.into_iter())
+                .map(|name| name.into().to_ascii_lowercase())
+                .collect(),
+            ..self
+        }
+    }
+
+    /// Set a flag indicating whether `X-Forwarded-For`, `X-Forwarded-Proto`,
+    /// and `X-Forwarded-Port` headers should be added to a request that
+    /// doesn't have them. ALB provides these headers, but API Gateway REST
+    /// APIs don't.
+    ///
+    /// `X-Forwarded-For` is taken from the source IP address in the request
+    /// context. `X-Forwarded-Proto` and `X-Forwarded-Port` are derived from
+    /// the scheme of the reconstructed request URI. Existing headers are left
+    /// as they are.
+    ///
+    /// The default value is `false`.
+    pub fn synthesize_forwarded_headers(self, value: bool) -> Self {
+        Self {
+            synthesize_forwarded_headers: value,
+            ..self
+        }
+    }
+
+    /// Enable the built-in responder for CORS preflight requests.
+    ///
+    /// When this is set, `OPTIONS` requests with an
+    /// `Access-Control-Request-Method` header are responded by the connector
+    /// without invoking the application: `204 No Content` with CORS headers if
+    /// the origin and the method are allowed, or `403 Forbidden` otherwise.
+    /// Other requests are passed to the application as usual.
+    ///
+    /// By default, preflight requests are passed to the application.
+    pub fn cors_preflight(self, value: CorsPreflight) -> Self {
+        Self {
+            cors_preflight: Some(value),
+            ..self
+        }
+    }
+
+    /// Set the `Content-Type` header value used for responses that have a
+    /// non-empty body but no `Content-Type` header. The header is set before
+    /// the function set by `binary_media_type_fn` is called.
+    ///
+    /// By default, such responses are left as they are.
+    pub fn default_content_type(self, value: impl Into<HeaderValue>) -> Self {
+        Self {
+            default_content_type: Some(value.into()),
+            ..self
+        }
+    }
+
+    /// Set the preferred cases of response header names (e.g.,
+    /// `X-Request-ID`).
+    ///
+    /// Response header names are lowercase by default. Header names matching
+    /// any of the given names case-insensitively are replaced with the given
+    /// names in a Lambda response.
+    pub fn header_name_case(self, value: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            header_name_case: value.into_iter().map(Into::into).collect(),
+            ..self
+        }
+    }
+
+    /// Set the headers whose values are masked in the connector's log output.
+    ///
+    /// The default value is `Authorization`, `Cookie`, `Set-Cookie`, and
+    /// `X-Api-Key`.
+    pub fn redact_headers(self, value: impl IntoIterator<Item = HeaderName>) -> Self {
+        Self {
+            redacted_headers: value.into_iter().collect(),
+            ..self
+        }
+    }
+
+    /// Set the query parameters whose values are masked in the connector's
+    /// log output. The names are matched case-insensitively.
+    ///
+    /// The default value is `access_token`, `api_key`, `password`, and
+    /// `token`.
+    pub fn redact_query_parameters(
+        self,
+        value: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        Self {
+            redacted_query_parameters: value.into_iter().map(Into::into).collect(),
+            ..self
+        }
+    }
+
+    /// Set a string prepended to the connector's log messages.
+    ///
+    /// The default value is an empty string.
+    pub fn log_prefix(self, value: impl Into<String>) -> Self {
+        Self {
+            log_prefix: value.into(),
+            ..self
+        }
+    }
+
+    /// Set a flag indicating whether the AWS request ID of the event being
+    /// processed should be included in the connector's log messages (after
+    /// `log_prefix`), such as `[8476a536-e9f4-11e8-9739-2dfe598c3fcd] `.
+    ///
+    /// The default value is `false`.
+    pub fn log_request_id(self, value: bool) -> Self {
+        Self {
+            log_request_id: value,
+            ..self
+        }
+    }
+
+    /// Set a flag indicating whether malformed request header values (e.g.,
+    /// ones including control characters) should be percent-encoded.
+    ///
+    /// Since `lambda_http` rejects an event including a malformed header,
+    /// such headers are removed with a warning by default. If this flag is
+    /// set, their values are percent-encoded instead. Headers with a malformed
+    /// name are always removed.
+    ///
+    /// The default value is `false`.
+    pub fn encode_invalid_header_values(self, value: bool) -> Self {
+        Self {
+            encode_invalid_header_values: value,
+            ..self
+        }
+    }
+
+    /// Set a predicate that, given a response header, returns a flag
+    /// indicating whether the header should be included in the Lambda
+    /// response. This is applied after hop-by-hop headers are removed (see
+    /// `keep_hop_by_hop_headers`).
+    ///
+    /// By default, all headers are included.
+    pub fn response_header_filter(
+        self,
+        value: impl FnMut(&HeaderName, &HeaderValue) -> bool + 'static,
+    ) -> Self {
+        Self {
+            response_header_filter: Box::new(value),
+            ..self
+        }
+    }
+
+    /// Set a flag indicating whether response headers whose names start with
+    /// `stripped_response_header_prefix` should be removed, e.g., to prevent
+    /// the application from setting the headers used by API Gateway
+    /// internally. Headers added by `default_response_headers` are kept, but
+    /// the `X-Amzn-Trace-Id` header added by `echo_trace_id` is subject to
+    /// this.
+    ///
+    /// The default value is `false`.
+    pub fn strip_response_header_prefix(self, value: bool) -> Self {
+        Self {
+            strip_response_header_prefix: value,
+            ..self
+        }
+    }
+
+    /// Set the prefix of the names of response headers removed by
+    /// `strip_response_header_prefix`. The prefix is matched
+    /// case-insensitively.
+    ///
+    /// The default value is `x-amzn-`.
+    pub fn stripped_response_header_prefix(self, value: impl Into<String>) -> Self {
+        Self {
+            stripped_response_header_prefix: value.into().to_ascii_lowercase(),
+            ..self
+        }
+    }
+
+    /// Set a flag indicating whether the error responses for actix-web's
+    /// extractor errors (e.g., `JsonPayloadError`) should have JSON bodies
+    /// like `{"error": "Json deserialize error: ..."}` instead of plain text.
+    ///
+    /// The default value is `false`.
+    pub fn json_error_bodies(self, value: bool) -> Self {
+        Self {
+            json_error_bodies: value,
+            ..self
+        }
+    }
+
+    /// Set a flag indicating whether the `Date` header should be added to a
+    /// response that doesn't have one, like actix-web's HTTP server does.
+    ///
+    /// The default value is `true`.
+    pub fn date_header(self, value: bool) -> Self {
+        Self {
+            date_header: value,
+            ..self
+        }
+    }
+
+    /// Set the maximum duration for reading a response body. If the body
+    /// stream doesn't complete in time, `500 Internal Server Error` is
+    /// returned instead.
+    ///
+    /// By default, there's no limit, and a body stream that never completes
+    /// stalls the function until the invocation times out.
+    pub fn body_read_timeout(self, value: Duration) -> Self {
+        Self {
+            body_read_timeout: Some(value),
+            ..self
+        }
+    }
+
+    /// Set a flag indicating whether `; charset=utf-8` should be appended to
+    /// the `Content-Type` header of a text response (i.e., one not encoded
+    /// as binary) if the media type is `text/*` or `application/json` and has
+    /// no `charset` parameter.
+    ///
+    /// The default value is `false`.
+    pub fn append_charset(self, value: bool) -> Self {
+        Self {
+            append_charset: value,
+            ..self
+        }
+    }
+
+    /// Set a flag indicating whether the peer address of a request (e.g.,
+    /// `HttpRequest::peer_addr`) should be set to the client's IP address.
+    ///
+    /// The address is taken from the source IP address in the request context
+    /// for API Gateway events and from the last entry of `X-Forwarded-For`
+    /// (which is appended by ALB) for ALB events. The port number is always
+    /// `0`.
+    ///
+    /// The default value is `false`, in which case the peer address is
+    /// unavailable.
+    pub fn peer_addr_from_source_ip(self, value: bool) -> Self {
+        Self {
+            peer_addr_from_source_ip: value,
+            ..self
+        }
+    }
+
+    /// Set a function that creates a response used in place of a response
+    /// whose body couldn't be read (e.g., the body stream produced an error
+    /// or `body_read_timeout` elapsed).
+    ///
+    /// By default, such a failure is treated as a handler error, which
+    /// usually results in `500 Internal Server Error`.
+    pub fn body_error_fallback(self, value: impl FnMut(&Error) -> Response + 'static) -> Self {
+        Self {
+            body_error_fallback: Some(Box::new(value)),
+            ..self
+        }
+    }
+
+    /// Set a flag indicating whether the `Content-Length` header should be
+    /// added to a response with a non-empty body that doesn't have one.
+    ///
+    /// An existing `Content-Length` header is always replaced with the actual
+    /// length of a non-empty body (before base64 encoding) regardless of this
+    /// flag. It's left intact if the body is empty so that responses to `HEAD`
+    /// requests can report the length.
+    ///
+    /// The default value is `false`.
+    pub fn set_content_length(self, value: bool) -> Self {
+        Self {
+            set_content_length: value,
+            ..self
+        }
+    }
+
+    /// Set a flag indicating whether the `Forwarded` header ([RFC 7239]) should
+    /// be added to a request that doesn't have one, e.g.,
+    /// `for=192.0.2.1;proto=https;host=example.com`.
+    ///
+    /// `for` is the client's IP address (see `peer_addr_from_source_ip`) and
+    /// omitted if unknown. `proto` and `host` are taken from the reconstructed
+    /// request URI. This is independent of `synthesize_forwarded_headers`.
+    ///
+    /// The default value is `false`.
+    ///
+    /// [RFC 7239]: https://tools.ietf.org/html/rfc7239
+    pub fn synthesize_rfc7239_forwarded(self, value: bool) -> Self {
+        Self {
+            synthesize_rfc7239_forwarded: value,
+            ..self
+        }
+    }
+
+    /// Set a path for health checks (e.g., `/health`). `GET` and `HEAD`
+    /// requests to the path (as it appears in an event) are responded with
+    /// the given status code by the connector without invoking the
+    /// application. Other checks such as `allowed_hosts` are skipped for them
+    /// because load balancers don't always send a proper `Host` header.
+    ///
+    /// By default, health check requests are passed to the application.
+    pub fn health_check(self, path: impl Into<String>, status: StatusCode) -> Self {
+        Self {
+            health_check: Some((path.into(), status)),
+            ..self
+        }
+    }
+
+    /// Set a flag indicating whether a request body compressed with `gzip` or
+    /// `deflate` (as indicated by `Content-Encoding` or `Transfer-Encoding`)
+    /// should be decompressed before being passed to the application. The
+    /// header is removed from such requests. Requests with a malformed body
+    /// are responded with `400 Bad Request`. `max_request_body_bytes` applies
+    /// to the decompressed body.
+    ///
+    /// `br` (brotli) is also supported if the `brotli` feature is enabled.
+    ///
+    /// The default value is `false`.
+    pub fn decompress_request_body(self, value: bool) -> Self {
+        Self {
+            decompress_request_body: value,
+            ..self
+        }
+    }
+
+    /// Set a flag indicating whether the body of a request with
+    /// `Content-Type: application/x-www-form-urlencoded` should be parsed
+    /// and inserted to the request's extensions as [`FormParams`]. The body
+    /// is still available to the application.
+    ///
+    /// The default value is `false`.
+    pub fn parse_form_body(self, value: bool) -> Self {
+        Self {
+            parse_form_body: value,
+            ..self
+        }
+    }
+
+    /// Set the maximum size of chunks in which a request body is supplied to
+    /// the application's payload stream, so that the application sees the
+    /// body as if it were streamed.
+    ///
+    /// Zero is treated as one. By default, the whole body is supplied as a
+    /// single chunk.
+    pub fn payload_chunk_size(self, value: usize) -> Self {
+        Self {
+            payload_chunk_size: Some(value),
+            ..self
+        }
+    }
+
+    /// Set the `Content-Type` header value used for error responses, i.e.,
+    /// responses rendered from handler errors (including extractor errors)
+    /// and error responses generated by the connector.
+    ///
+    /// By default, the content type set by the error's `render_response` (or
+    /// `text/plain` for the connector's error responses) is used.
+    pub fn error_content_type(self, value: impl Into<HeaderValue>) -> Self {
+        Self {
+            error_content_type: Some(value.into()),
+            ..self
+        }
+    }
+
+    /// Set a template used as the body of error responses (see
+    /// `error_content_type`). `{status}` and `{reason}` in the template are
+    /// replaced with the status code (e.g., `404`) and its canonical reason
+    /// phrase (e.g., `Not Found`), respectively.
+    ///
+    /// By default, the body rendered by the error is used.
+    pub fn error_body_template(self, value: impl Into<String>) -> Self {
+        Self {
+            error_body_template: Some(value.into()),
+            ..self
+        }
+    }
+
+    /// Set a function that verifies a request body before the request is
+    /// passed to the application, e.g., to check the signature of a webhook.
+    /// The function receives the request head and the body exactly as
+    /// received (after base64 decoding, but before `decompress_request_body`
+    /// is applied). If the function returns `Err`, the returned response is
+    /// sent instead of calling the application.
+    ///
+    /// By default, request bodies aren't verified.
+    pub fn verify_body(
+        self,
+        value: impl FnMut(&RequestHead, &[u8]) -> Result<(), Response<Bytes>> + 'static,
+    ) -> Self {
+        Self {
+            verify_body: Some(Box::new(value)),
+            ..self
+        }
+    }
+
+    /// Set a function that transforms a request body before it's passed to
+    /// the application, e.g., to unwrap an envelope added by an upstream
+    /// system. The function is called after `verify_body` and can change the
+    /// length of the body (`Content-Length` is updated accordingly). If the
+    /// function returns `Err`, it's treated in the same way as an error
+    /// returned by the application.
+    ///
+    /// By default, request bodies are passed as they are.
+    pub fn map_request_body(
+        self,
+        value: impl FnMut(&RequestHead, Bytes) -> Result<Bytes, Error> + 'static,
+    ) -> Self {
+        Self {
+            map_request_body: Some(Box::new(value)),
+            ..self
+        }
+    }
+
+    /// Set a function that modifies a request converted from an event before
+    /// any other processing by the connector, e.g., to rewrite a header or
+    /// add query parameters. The changes are visible to all subsequent steps,
+    /// such as the reconstruction of the request URI. Query parameters can be
+    /// added by setting a query string in the request URI, which is preserved
+    /// in addition to `queryStringParameters`.
+    ///
+    /// By default, requests are processed as they are.
+    pub fn map_lambda_request(
+        self,
+        value: impl FnMut(lambda_http::Request, &lambda_runtime::Context) -> lambda_http::Request
+            + 'static,
+    ) -> Self {
+        Self {
+            map_lambda_request: Some(Box::new(value)),
+            ..self
+        }
+    }
+
+    /// Add a value inserted to the extensions of every request, e.g.,
+    /// configuration resolved from environment variables at startup. Each
+    /// request receives a clone of `value`, so wrap it with `Arc` if cloning
+    /// is expensive.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use actix_service::Service;
+    /// use actix_web::{web, App, HttpRequest};
+    /// use futures::Future;
+    ///
+    /// #[derive(Clone)]
+    /// struct Config {
+    ///     bucket: String,
+    /// }
+    ///
+    /// fn index(req: HttpRequest) -> String {
+    ///     req.extensions().get::<Config>().unwrap().bucket.clone()
+    /// }
+    ///
+    /// // e.g., from `std::env::var`
+    /// let config = Config {
+    ///     bucket: "my-bucket".to_owned(),
+    /// };
+    ///
+    /// actix_lambda_http::LambdaHttpServer::new(|| {
+    ///     App::new().route("/", web::get().to(index))
+    /// })
+    /// .with_extension(config)
+    /// .run_service(|service| {
+    ///     let req = lambda_http::http::Request::builder()
+    ///         .uri("https://example.com/")
+    ///         .body(lambda_http::Body::Empty)
+    ///         .unwrap();
+    ///     let resp = service.call(req).wait().unwrap();
+    ///     assert_eq!(resp.body(), &lambda_http::Body::from("my-bucket"));
+    /// })
+    /// .unwrap();
+    /// ```
+    pub fn with_extension<T: Clone + 'static>(self, value: T) -> Self {
+        let mut request_extensions = self.request_extensions;
+        request_extensions.push(Box::new(move |extensions| extensions.insert(value.clone())));
+        Self {
+            request_extensions,
+            ..self
+        }
+    }
+
+    /// Set the headers added to every response, including error responses
+    /// generated by the connector. A header is added only if the response
+    /// doesn't have a header of the same name.
+    ///
+    /// The default value is empty.
+    pub fn default_response_headers(
+        self,
+        value: impl IntoIterator<Item = (HeaderName, HeaderValue)>,
+    ) -> Self {
+        Self {
+            default_response_headers: value.into_iter().collect(),
+            ..self
+        }
+    }
+
+    /// Set a function that modifies a response before it's converted to a
+    /// Lambda response. The function is called after the response body is
+    /// read and the connector's own header processing (such as
+    /// `default_content_type`) is done, but before the encoding of the body is
+    /// determined, so it can change the headers that affect the encoding (e.g.,
+    /// `Content-Type`).
+    ///
+    /// By default, responses are left as they are.
+    pub fn map_response(self, value: impl FnMut(&mut Response<Bytes>) + 'static) -> Self {
+        Self {
+            map_response: Some(Box::new(value)),
+            ..self
+        }
+    }
+
+    /// Start polling for API gateway and ALB events.
+    ///
+    /// # Panics
+    ///
+    /// See [`lambda_runtime::start`].
+    pub fn start(self) -> Result<(), StartError<S::InitError>> {
+        // See `lean_runtime`
+        let lambda_rt = if self.lean_runtime {
+            Some(
+                tokio::runtime::Builder::new()
+                    .core_threads(1)
+                    .blocking_threads(1)
+                    .build()
+                    .map_err(StartError::Runtime)?,
+            )
+        } else {
+            None
+        };
+
+        self.run(|handler| lambda_runtime::start(handler, lambda_rt))
+            .map_err(StartError::Init)
+    }
+
+    /// Process a single event read from `input` as JSON and write the response
+    /// to `output` as JSON, without using the Lambda runtime API. This is
+    /// useful for testing an application locally.
+    ///
+    /// Requires the `local` feature.
+    ///
+    /// # Examples
+    ///
+    /// Running a single synthetic invocation in an integration test:
+    ///
+    /// ```
+    /// use actix_web::{web, App};
+    ///
+    /// let event = r#"{
+    ///     "httpMethod": "GET",
+    ///     "path": "/hello",
+    ///     "headers": { "host": "example.com" },
+    ///     "queryStringParameters": null,
+    ///     "requestContext": { "elb": { "targetGroupArn": "arn" } },
+    ///     "body": null,
+    ///     "isBase64Encoded": false
+    /// }"#;
+    ///
+    /// let mut output = Vec::new();
+    /// actix_lambda_http::LambdaHttpServer::new(|| {
+    ///     App::new().route("/hello", web::get().to(|| "hello"))
+    /// })
+    /// .run_local(event.as_bytes(), &mut output)
+    /// .unwrap();
+    ///
+    /// let response: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    /// assert_eq!(response["statusCode"], 200);
+    /// assert_eq!(response["body"], "hello");
+    /// ```
+    #[cfg(feature = "local")]
+    pub fn run_local(
+        self,
+        input: impl std::io::Read,
+        output: impl std::io::Write,
+    ) -> Result<(), LocalError<S::InitError>> {
+        let mut result = Ok(());
+        self.run(|handler| {
+            result = (|| {
+                let event = serde_json::from_reader(input).map_err(LocalError::Json)?;
+                let resp = handler(event, lambda_runtime::Context::default())
+                    .map_err(LocalError::Handler)?;
+                serde_json::to_writer_pretty(output, &resp).map_err(LocalError::Json)
+            })();
+        })
+        .map_err(LocalError::Init)?;
+        result
+    }
+
+    /// Construct the application service and pass a [`LambdaHttpService`] to
+    /// `runner` instead of polling for events. This is useful for wrapping the
+    /// conversion with other services (e.g., to collect metrics) or for
+    /// testing.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use actix_service::Service;
+    /// use actix_web::{web, App};
+    /// use futures::Future;
+    /// use lambda_http::http::StatusCode;
+    ///
+    /// actix_lambda_http::LambdaHttpServer::new(|| {
+    ///     App::new().route("/hello", web::get().to(|| "hello"))
+    /// })
+    /// .run_service(|service| {
+    ///     let req = lambda_http::http::Request::builder()
+    ///         .uri("https://example.com/hello")
+    ///         .body(lambda_http::Body::Empty)
+    ///         .unwrap();
+    ///     let resp = service.call(req).wait().unwrap();
+    ///     assert_eq!(resp.status(), StatusCode::OK);
+    ///     assert_eq!(resp.body(), &lambda_http::Body::from("hello"));
+    /// })
+    /// .unwrap();
+    /// ```
+    pub fn run_service(
+        self,
+        runner: impl FnOnce(&mut LambdaHttpService<'_>),
+    ) -> Result<(), S::InitError> {
+        self.run_inner(runner)
+    }
+
+    /// Construct the application service and pass an event handler to
+    /// `runner`.
+    fn run(mut self, runner: impl FnOnce(&mut EventHandler<'_>)) -> Result<(), S::InitError> {
+        let default_authority = self.default_authority.clone();
+        let encode_invalid_header_values = self.encode_invalid_header_values;
+        let max_request_body_bytes = self.max_request_body_bytes;
+        let alb_cookie_case_hack = self.alb_cookie_case_hack;
+        let header_name_case = take(&mut self.header_name_case);
+        let log_prefix = self.log_prefix.clone();
+        let log_request_id = self.log_request_id;
+        let redacted_query_parameters = self.redacted_query_parameters.clone();
+        let mut warned_missing_host = false;
+
+        self.run_inner(|service| {
+            // Deserialize events by ourselves instead of using
+            // `lambda_http::start`, which panics on some valid events
+            runner(&mut |event: serde_json::Value,
+                         ctx: lambda_runtime::Context|
+             -> Result<response::LambdaResponse, HandlerError> {
+                // See `log_prefix` and `log_request_id`
+                log_context::set_prefix(&log_prefix, log_request_id, &ctx);
+
+                let source = request::event_source(&event);
+                let multi_value_headers =
+                    (event.get("multiValueHeaders")).is_some_and(serde_json::Value::is_object);
+
+                let input = match request::from_event(
+                    event,
+                    &default_authority,
+                    encode_invalid_header_values,
+                    max_request_body_bytes,
+                ) {
+                    Ok(event) => {
+                        if event.used_default_authority && !warned_missing_host {
+                            warn!(
+                                "Got an event without a `Host` header, using {:?} as the \
+                                     authority (this warning is displayed only once)",
+                                default_authority
+                            );
+                            warned_missing_host = true;
+                        }
+
+                        Ok(event.request)
+                    }
+                    Err(request::EventError::Json(e)) => return Err(e.into()),
+                    Err(request::EventError::NotHttp(e)) => {
+                        warn!("Rejecting the event: {}", e);
+                        return Err(HandlerError::new(e));
+                    }
+                    Err(request::EventError::MalformedUri(uri)) => {
+                        debug!(
+                            "The event has a malformed URI {:?}, returning 400",
+                            RedactedQuery(&uri, &redacted_query_parameters)
+                        );
+                        Err(error_response(
+                            StatusCode::BAD_REQUEST,
+                            "Malformed request URI",
+                        ))
+                    }
+                    Err(request::EventError::BodyTooLarge(len)) => {
+                        debug!(
+                            "The request body is too large ({} bytes after decoding), \
+                                 returning 413",
+                            len
+                        );
+                        Err(error_response(
+                            StatusCode::PAYLOAD_TOO_LARGE,
+                            "Payload too large",
+                        ))
+                    }
+                    Err(request::EventError::MalformedBody(e)) => {
+                        debug!(
+                            "Failed to decode the base64-encoded body ({:?}), returning 400",
+                            e
+                        );
+                        Err(error_response(
+                            StatusCode::BAD_REQUEST,
+                            "Malformed request body",
+                        ))
+                    }
+                };
+
+                let resp = service.respond(input, ctx)?;
+                let mut resp =
+                    response::LambdaResponse::from_response(source, multi_value_headers, resp);
+
+                // See `header_name_case`
+                if !header_name_case.is_empty() {
+                    resp.apply_header_name_case(&header_name_case);
+                }
+
+                // See `alb_cookie_case_hack`
+                if alb_cookie_case_hack && source == EventSource::Alb && !multi_value_headers {
+                    resp.permute_set_cookie_case();
+                }
+
+                Ok(resp)
+            })
+        })
+    }
+
+    /// Construct the application service and pass a [`LambdaHttpService`] to
+    /// `runner`.
+    fn run_inner(
+        self,
+        runner: impl FnOnce(&mut LambdaHttpService<'_>),
+    ) -> Result<(), S::InitError> {
+        // TODO: Check possible causes of `new` failure
+        let mut rt = actix_rt::Runtime::new().unwrap();
+
+        let cfg = ServerConfig::new("127.0.0.1:8080".parse().unwrap());
+
+        // API Gateway only accepts HTTPS (see `default_scheme`)
+        if self.default_scheme != uri::Scheme::HTTP {
+            cfg.set_secure();
+        }
+
+        // See `new_with_retries`
+        let mut factory = Some(self.factory);
+        let mut retries_left = self.init_retries;
+        let mut service = loop {
+            let app = match self.call_factory_mut {
+                Some(call_factory_mut) => call_factory_mut(factory.as_mut().unwrap()),
+                None => (factory.take().unwrap())(),
+            };
+            let new_service = app.into_new_service();
+            match rt.block_on(new_service.new_service(&cfg)) {
+                Ok(service) => break service,
+                Err(_) if retries_left > 0 => {
+                    retries_left -= 1;
+                    warn!(
+                        "Failed to construct the service, retrying ({} retries left)",
+                        retries_left
+                    );
+                }
+                Err(e) => return Err(e),
+            }
+        };
+
+        // See `on_init`
+        if let Some(on_init) = self.on_init {
+            on_init(&service);
+        }
+
+        let mut binary_media_type_fn = self.binary_media_type_fn;
+        let host_header_authority = self.host_header_authority;
+        let sync_host_header = self.sync_host_header;
+        let base_path = self.base_path;
+        let pass_through_unmounted_paths = self.pass_through_unmounted_paths;
+        let default_scheme = self.default_scheme;
+        let max_request_body_bytes = self.max_request_body_bytes;
+        let max_uri_length = self.max_uri_length;
+        let max_headers = self.max_headers;
+        let max_header_bytes = self.max_header_bytes;
+        let normalize_invalid_responses = self.normalize_invalid_responses;
+        let use_raw_path = self.use_raw_path;
+        let mut query_encoding = self.query_encoding;
+        let query_array_style = self.query_array_style;
+        let trailing_slash = self.trailing_slash;
+        let strip_stage = self.strip_stage;
+        let rewrite_redirect_locations = self.rewrite_redirect_locations;
+        let detect_base_path = self.detect_base_path;
+        let mut uri_transform = self.uri_transform;
+        let conditional_get = self.conditional_get;
+        let path_from_proxy_parameter = self.path_from_proxy_parameter;
+        let merge_slashes = self.merge_slashes;
+        let allowed_hosts = self.allowed_hosts;
+        let honor_method_override = self.honor_method_override;
+        let propagate_trace_id = self.propagate_trace_id;
+        let echo_trace_id = self.echo_trace_id;
+        let kept_hop_by_hop_headers = self.kept_hop_by_hop_headers;
+        let synthesize_forwarded_headers = self.synthesize_forwarded_headers;
+        let cors_preflight = self.cors_preflight;
+        let default_content_type = self.default_content_type;
+        let redacted_headers = self.redacted_headers;
+        let redacted_query_parameters = self.redacted_query_parameters;
+        let log_prefix = self.log_prefix;
+        let log_request_id = self.log_request_id;
+        let mut response_header_filter = self.response_header_filter;
+        let json_error_bodies = self.json_error_bodies;
+        let date_header = self.date_header;
+        let body_read_timeout = self.body_read_timeout;
+        let append_charset = self.append_charset;
+        let binary_accept_types = self.binary_accept_types;
+        let peer_addr_from_source_ip = self.peer_addr_from_source_ip;
+        let mut body_error_fallback = self.body_error_fallback;
+        let set_content_length = self.set_content_length;
+        let mut map_response = self.map_response;
+        let default_response_headers = self.default_response_headers;
+        let synthesize_rfc7239_forwarded = self.synthesize_rfc7239_forwarded;
+        let health_check = self.health_check;
+        let decompress_request_body = self.decompress_request_body;
+        let parse_form_body = self.parse_form_body;
+        let payload_chunk_size = self.payload_chunk_size;
+        let error_content_type = self.error_content_type;
+        let error_body_template = self.error_body_template;
+        let mut verify_body = self.verify_body;
+        let mut map_request_body = self.map_request_body;
+        let strip_response_header_prefix = self.strip_response_header_prefix;
+        let stripped_response_header_prefix = self.stripped_response_header_prefix;
+        let mut map_lambda_request = self.map_lambda_request;
+        let utf8_binary_as_text = self.utf8_binary_as_text;
+        let request_extensions = self.request_extensions;
+
+        // The handler is `FnMut` (doesn't have to be `Fn + 'static`)
+        let lambda_http_handler = |mut req: lambda_http::Request, ctx: lambda_runtime::Context| {
+            // See `map_lambda_request`
+            if let Some(map_lambda_request) = &mut map_lambda_request {
+                req = map_lambda_request(req, &ctx);
+            }
+
+            // `request::from_event` decodes a base64-encoded body into `Binary`
+            let event_body = match req.body() {
+                LambdaBody::Empty => EventBody::Absent,
+                LambdaBody::Text(_) => EventBody::Text,
+                LambdaBody::Binary(_) => EventBody::Binary,
+            };
+
+            // See `health_check`
+            if let Some((path, status)) = &health_check {
+                if (req.method() == Method::GET || req.method() == Method::HEAD)
+                    && req.uri().path() == path
+                {
+                    debug!("Responding to the health check with {}", status);
+                    return status_response(*status);
+                }
+            }
+
+            // Check the headers (see `max_headers` and `max_header_bytes`)
+            let num_headers = req.headers().len();
+            if max_headers.is_some_and(|max| num_headers > max) {
+                debug!(
+                    "The request has too many headers ({}), returning 431",
+                    num_headers
+                );
+                return error_response(
+                    StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+                    "Request header fields too large",
+                );
+            }
+            if let Some(max) = max_header_bytes {
+                let header_bytes: usize = (req.headers().iter())
+                    .map(|(name, value)| name.as_str().len() + value.len())
+                    .sum();
+                if header_bytes > max {
+                    debug!(
+                        "The request headers are too large ({} bytes), returning 431",
+                        header_bytes
+                    );
+                    return error_response(
+                        StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+                        "Request header fields too large",
+                    );
+                }
+            }
+
+            // See `allowed_hosts`
+            if let Some(allowed_hosts) = &allowed_hosts {
+                let host = (req.headers().get(HOST))
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.parse::<uri::Authority>().ok())
+                    .map(|authority| authority.host().to_owned())
+                    .or_else(|| req.uri().host().map(str::to_owned))
+                    .unwrap_or_default();
+                if !allowed_hosts
+                    .iter()
+                    .any(|pattern| host_matches(pattern, &host))
+                {
+                    debug!("The host {:?} is not allowed, returning 421", host);
+                    return status_response(StatusCode::MISDIRECTED_REQUEST);
+                }
+            }
+
+            // See `cors_preflight`
+            if let Some(cors_preflight) = &cors_preflight {
+                if req.method() == Method::OPTIONS {
+                    if let Some(resp) = cors_preflight.respond(req.headers()) {
+                        return resp;
+                    }
+                }
+            }
+
+            // Split the original path into the prefix removed by API Gateway
+            // and the rest
+            let raw_path = (req.extensions().get::<request::RawPath>()).and_then(|raw_path| {
+                let found = find_raw_path(&raw_path.0, req.uri().path());
+                if found.is_none() {
+                    debug!(
+                        "The original path {:?} doesn't match the path {:?}, ignoring",
+                        raw_path.0,
+                        req.uri().path()
+                    );
+                }
+                found.map(|suffix| raw_path.0.split_at(raw_path.0.len() - suffix.len()))
+            });
+
+            // See `detect_base_path`
+            let detected_base_path = raw_path
+                .filter(|_| detect_base_path)
+                .map(|(prefix, _)| prefix.to_owned())
+                .filter(|prefix| !prefix.is_empty());
+
+            // See `path_from_proxy_parameter`
+            let proxy_path = match req.request_context() {
+                RequestContext::ApiGateway { resource_path, .. }
+                    if path_from_proxy_parameter && resource_path.contains("{proxy+}") =>
+                {
+                    expand_resource_path(&resource_path, &req.path_parameters())
+                }
+                _ => None,
+            };
+
+            // Use the original path if possible (see `use_raw_path`)
+            let mut path = match (proxy_path, raw_path) {
+                (Some(proxy_path), _) => proxy_path,
+                (None, Some((_, suffix))) if use_raw_path => suffix.to_string(),
+                _ => req.uri().path().to_string(),
+            };
+
+            // The path from the event shouldn't include a query string or a
+            // fragment, but if it does, keep the query string separately so
+            // that the query parameters can be appended to it later. The
+            // fragment is never sent to servers, so discard it. The event URI
+            // may also have a query string that wasn't parsed into
+            // `queryStringParameters`. It's already percent-encoded because
+            // `lambda_http` managed to parse the URI.
+            if let Some(i) = path.find('#') {
+                debug!(
+                    "Removing the fragment from the path {:?}",
+                    RedactedQuery(&path, &redacted_query_parameters)
+                );
+                path.truncate(i);
+            }
+            let path_query = match path.find('?') {
+                Some(i) => {
+                    let query = path[i + 1..].to_string();
+                    path.truncate(i);
+                    Some(query)
+                }
+                None => req.uri().query().map(str::to_owned),
+            }
+            .filter(|query| !query.is_empty());
+
+            // See `merge_slashes`
+            if merge_slashes && path.contains("//") {
+                let mut merged = String::with_capacity(path.len());
+                for c in path.chars() {
+                    if !(c == '/' && merged.ends_with('/')) {
+                        merged.push(c);
+                    }
+                }
+                path = merged;
+            }
+
+            // Remove the stage name (see `strip_stage`)
+            if strip_stage {
+                if let RequestContext::ApiGateway { stage, .. } = req.request_context() {
+                    if !stage.is_empty() && stage != "$default" {
+                        if let Some(stripped) = strip_base_path(&path, &format!("/{}", stage)) {
+                            debug!("Removing the stage name {:?} from the path", stage);
+                            path = stripped.to_string();
+                        }
+                    }
+                }
+            }
+
+            // Remove the base path (see `mount_base_path`)
+            let mut mounted_base_path = None;
+            if let Some(base_path) = &base_path {
+                match strip_base_path(&path, base_path) {
+                    Some(stripped) => {
+                        path = stripped.to_string();
+                        mounted_base_path = Some(base_path);
+                    }
+                    None if pass_through_unmounted_paths => {}
+                    None => {
+                        debug!(
+                            "The path {:?} is not under the base path {:?}, returning 404",
+                            path, base_path
+                        );
+                        return status_response(StatusCode::NOT_FOUND);
+                    }
+                }
+            }
+
+            // Keep the original body for `verify_body`
+            let raw_body = verify_body
+                .as_ref()
+                .map(|_| Bytes::from(req.body().as_ref()));
+
+            // See `decompress_request_body`. Transfer codings are applied on
+            // top of content codings, so they are undone first.
+            for header in &[TRANSFER_ENCODING, CONTENT_ENCODING] {
+                // e.g., `gzip, chunked`. `chunked` is irrelevant here because
+                // the body is already complete.
+                let codings: Vec<String> = (req.headers().get(header))
+                    .filter(|_| decompress_request_body)
+                    .and_then(|value| value.to_str().ok())
+                    .map(|value| {
+                        (value.split(','))
+                            .map(|coding| coding.trim().to_ascii_lowercase())
+                            .filter(|coding| coding != "chunked" && coding != "identity")
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                let encoding = match &codings[..] {
+                    [encoding] => encoding,
+                    _ => continue,
+                };
+
+                // Stop decompressing as soon as the limit is exceeded
+                let limit =
+                    max_request_body_bytes.map_or(u64::MAX, |max| (max as u64).saturating_add(1));
+                match decompress(req.body(), encoding, limit) {
+                    Some(Ok(bytes)) => {
+                        debug!(
+                            "Decompressed the {} request body ({} bytes, {})",
+                            encoding,
+                            bytes.len(),
+                            header
+                        );
+                        *req.body_mut() = LambdaBody::Binary(bytes);
+                        req.headers_mut().remove(header);
+                    }
+                    Some(Err(e)) => {
+                        debug!("Failed to decompress the request body: {:?}", e);
+                        return error_response(StatusCode::BAD_REQUEST, "Malformed request body");
+                    }
+                    None => {}
+                }
+            }
+
+            // Check the body size (see `max_request_body_bytes`)
+            let body_len = req.body().len();
+            if max_request_body_bytes.is_some_and(|max| body_len > max) {
+                debug!(
+                    "The request body is too large ({} bytes), returning 413",
+                    body_len
+                );
+                return error_response(StatusCode::PAYLOAD_TOO_LARGE, "Payload too large");
+            }
+
+            // Reconstruct the URI
+            let uri = {
+                let mut builder = uri::Builder::new();
+
+                // `X-Forwarded-Proto` takes precedence over `default_scheme`
+                let forwarded_scheme = (req.headers().get("x-forwarded-proto"))
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.parse::<uri::Scheme>().ok());
+                builder.scheme(forwarded_scheme.unwrap_or_else(|| default_scheme.clone()));
+
+                // The `Host` header takes precedence over the event URI
+                // (see `host_header_authority`)
+                let host_authority = if host_header_authority {
+                    (req.headers().get(HOST))
+                        .and_then(|value| value.to_str().ok())
+                        .and_then(|value| value.parse::<uri::Authority>().ok())
+                } else {
+                    None
+                };
+                match host_authority.or_else(|| req.uri().authority_part().cloned()) {
+                    Some(authority) => builder.authority(authority),
+                    None => {
+                        debug!(
+                            "The event URI {:?} has no authority, returning 400",
+                            RedactedQuery(&req.uri().to_string(), &redacted_query_parameters)
+                        );
+                        return error_response(StatusCode::BAD_REQUEST, "Malformed request URI");
+                    }
+                };
+
+                // See `trailing_slash`
+                match trailing_slash {
+                    TrailingSlash::Keep => {}
+                    TrailingSlash::Trim => {
+                        let len = path.trim_end_matches('/').len();
+                        path.truncate(len.max(1));
+                    }
+                    TrailingSlash::Append => {
+                        if !path.ends_with('/') {
+                            path.push('/');
+                        }
+                    }
+                }
+
+                // The original path may include unencoded non-ASCII characters
+                // (see `use_raw_path`)
+                if !path.is_ascii() {
+                    path = utf8_percent_encode(&path, enc_set::NON_ASCII_ENCODE).to_string();
+                }
+
+                // Reconstruct the encoded query parameters
+                // (see `query_array_style`). They are appended to the existing
+                // query string, if any.
+                let query_params = req.query_string_parameters();
+                let mut first = true;
+                if let Some(query) = &path_query {
+                    path.push('?');
+                    path.push_str(query);
+                    first = false;
+                }
+                for (key, _) in query_params.iter() {
+                    let values = query_params.get_all(key).unwrap_or_default();
+                    if values.is_empty() {
+                        continue;
+                    }
+                    let mut add = |path: &mut String, key: &str, values: &[&str]| {
+                        path.push(if replace(&mut first, false) { '?' } else { '&' });
+                        query_encoding.encode(key, path);
+                        path.push('=');
+                        for (i, value) in values.iter().enumerate() {
+                            if i > 0 {
+                                path.push(',');
+                            }
+                            query_encoding.encode(value, path);
+                        }
+                    };
+                    match query_array_style {
+                        _ if values.len() == 1 => add(&mut path, key, &values),
+                        QueryArrayStyle::Repeat => {
+                            for value in &values {
+                                add(&mut path, key, &[value]);
+                            }
+                        }
+                        QueryArrayStyle::Brackets => {
+                            let key = format!("{}[]", key);
+                            for value in &values {
+                                add(&mut path, &key, &[value]);
+                            }
+                        }
+                        QueryArrayStyle::Comma => add(&mut path, key, &values),
+                    }
+                }
+
+                // Check the URI length (see `max_uri_length`)
+                if max_uri_length.is_some_and(|max| path.len() > max) {
+                    debug!(
+                        "The request URI is too long ({} bytes), returning 414",
+                        path.len()
+                    );
+                    return error_response(StatusCode::URI_TOO_LONG, "URI too long");
+                }
+
+                builder.path_and_query(path.as_str());
+
+                debug!(
+                    "Original URI = {:?}, query string parameters = {:?}",
+                    RedactedQuery(&req.uri().to_string(), &redacted_query_parameters),
+                    RedactedParams(&query_params, &redacted_query_parameters)
+                );
+
+                builder.build()
+            };
+
+            let uri = match uri {
+                Ok(uri) => uri,
+                Err(e) => {
+                    debug!(
+                        "Failed to reconstruct the URI from the path {:?}: {:?}",
+                        RedactedQuery(&path, &redacted_query_parameters),
+                        e
+                    );
+                    return error_response(StatusCode::BAD_REQUEST, "Malformed request URI");
+                }
+            };
+
+            // See `uri_transform`
+            let uri = match &mut uri_transform {
+                Some(uri_transform) => match uri_transform(uri, &req) {
+                    Ok(uri) => uri,
+                    Err(e) => {
+                        debug!("`uri_transform` failed: {:?}", e);
+                        return error_response(StatusCode::BAD_REQUEST, "Malformed request URI");
+                    }
+                },
+                None => uri,
+            };
+
+            debug!(
+                "Reconstructed URI = {:?}",
+                RedactedQuery(&uri.to_string(), &redacted_query_parameters)
+            );
+            debug!(
+                "Request headers = {:?}",
+                RedactedHeaders(req.headers(), &redacted_headers)
+            );
+
+            // See `sync_host_header`
+            if sync_host_header {
+                if let Some(authority) = uri.authority_part() {
+                    if req.headers().get(HOST).map(|value| value.as_bytes())
+                        != Some(authority.as_str().as_bytes())
+                    {
+                        match HeaderValue::from_str(authority.as_str()) {
+                            Ok(value) => {
+                                debug!("Setting the `Host` header to {:?}", authority);
+                                req.headers_mut().insert(HOST, value);
+                            }
+                            Err(_) => {
+                                debug!("Can't set the `Host` header to {:?}, ignoring", authority);
+                            }
+                        }
+                    }
+                }
+            }
+
+            // See `peer_addr_from_source_ip`
+            let peer_addr = if peer_addr_from_source_ip {
+                let source_ip = source_ip(&req);
+                if source_ip.is_none() {
+                    debug!("Couldn't determine the source IP address");
+                }
+                source_ip.map(|ip| SocketAddr::new(ip, 0))
+            } else {
+                None
+            };
+
+            // See `synthesize_forwarded_headers`
+            if synthesize_forwarded_headers {
+                let source_ip = match req.request_context() {
+                    RequestContext::ApiGateway { identity, .. } => Some(identity.source_ip),
+                    RequestContext::Alb { .. } => None,
+                };
+                let scheme = uri.scheme_str().unwrap_or("https");
+                let port = if scheme == "http" { "80" } else { "443" };
+
+                let headers = req.headers_mut();
+                let mut add = |name: &'static str, value: &str| {
+                    if !headers.contains_key(name) {
+                        if let Ok(value) = HeaderValue::from_str(value) {
+                            headers.insert(name, value);
+                        }
+                    }
+                };
+                if let Some(source_ip) = source_ip.filter(|ip| !ip.is_empty()) {
+                    add("x-forwarded-for", &source_ip);
+                }
+                add("x-forwarded-proto", scheme);
+                add("x-forwarded-port", port);
+            }
+
+            // See `synthesize_rfc7239_forwarded`
+            if synthesize_rfc7239_forwarded && !req.headers().contains_key(FORWARDED) {
+                let mut elements = Vec::new();
+                match source_ip(&req) {
+                    Some(IpAddr::V4(ip)) => elements.push(format!("for={}", ip)),
+                    Some(IpAddr::V6(ip)) => elements.push(format!("for=\"[{}]\"", ip)),
+                    None => {}
+                }
+                elements.push(format!("proto={}", uri.scheme_str().unwrap_or("https")));
+                if let Some(authority) = uri.authority_part() {
+                    elements.push(format!("host={}", forwarded_value(authority.as_str())));
+                }
+                if let Ok(value) = HeaderValue::from_str(&elements.join(";")) {
+                    req.headers_mut().insert(FORWARDED, value);
+                }
+            }
+
+            // See `binary_by_accept`
+            let accepts_binary = !binary_accept_types.is_empty()
+                && (req.headers().get(ACCEPT))
                     .and_then(|value| value.to_str().ok())
-                    .unwrap_or("");
-                let is_binary = binary_media_type_fn(content_type);
+                    .is_some_and(|accept| accept_matches(accept, &binary_accept_types));
 
-                debug!(
-                    "Encoding the response body as {} for content type {:?}",
-                    if is_binary { "binary" } else { "text" },
-                    content_type
-                );
+            // See `conditional_get`
+            let if_none_match = if conditional_get
+                && (req.method() == Method::GET || req.method() == Method::HEAD)
+            {
+                req.headers().get(IF_NONE_MATCH).cloned()
+            } else {
+                None
+            };
 
-                let resp_body = if is_binary {
-                    LambdaBody::Binary(resp_body_vec)
-                } else {
-                    LambdaBody::Text(String::from_utf8(resp_body_vec)?)
+            // API Gateway doesn't always include `Content-Length`, and the
+            // original one may not match the body we actually have (e.g., the
+            // body was removed or base64-decoded), which would confuse actix's
+            // payload reading. Since the whole body is known, always set it
+            // (`0` for an empty body) unless the event had no body at all.
+            if event_body == EventBody::Absent {
+                if let Some(value) = req.headers_mut().remove(CONTENT_LENGTH) {
+                    debug!(
+                        "Removing the `Content-Length` {:?} of the request without a body",
+                        value
+                    );
+                }
+            } else {
+                if let Some(value) = req.headers().get(CONTENT_LENGTH) {
+                    if value.to_str().ok().and_then(|x| x.parse::<usize>().ok()) != Some(body_len) {
+                        debug!(
+                            "Replacing the stale `Content-Length` {:?} with {}",
+                            value, body_len
+                        );
+                    }
+                }
+                req.headers_mut().insert(CONTENT_LENGTH, body_len.into());
+            }
+
+            // The body isn't chunked anymore, and `Transfer-Encoding` would
+            // take precedence over the `Content-Length` set above
+            if let Some(value) = req.headers_mut().remove(TRANSFER_ENCODING) {
+                debug!("Removing the `Transfer-Encoding` header {:?}", value);
+            }
+
+            // The body is already complete, so `Expect: 100-continue` is
+            // meaningless here. Remove it so that the application doesn't try
+            // to handle it.
+            let expect = req.headers_mut().remove(EXPECT);
+            if let Some(expect) = &expect {
+                debug!("Removing the `Expect` header {:?}", expect);
+            }
+
+            // See `parse_form_body`
+            let is_form = (req.headers().get(CONTENT_TYPE))
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<mime::Mime>().ok())
+                .is_some_and(|mime| mime.essence_str() == "application/x-www-form-urlencoded");
+            let form_params = if parse_form_body && is_form {
+                match serde_urlencoded::from_bytes(req.body()) {
+                    Ok(params) => Some(FormParams(params)),
+                    Err(e) => {
+                        debug!("Failed to parse the form body, ignoring: {:?}", e);
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+
+            // Construct `actix_http::Payload`
+            let body: Bytes = match take(req.body_mut()) {
+                LambdaBody::Empty => Bytes::new(),
+                LambdaBody::Text(text) => text.into(),
+                LambdaBody::Binary(bytes) => bytes.into(),
+            };
+            let (mut payload_sender, payload) = actix_http::h1::Payload::create(false);
+            let mut actix_req: Request = Request::with_payload(payload.into());
+
+            // Set the headers. Malformed headers in the event were already
+            // removed or percent-encoded by `request::from_event` (see
+            // `encode_invalid_header_values`), so this conversion is
+            // infallible.
+            let actix_req_head = actix_req.head_mut();
+            actix_req_head.method = req.method().clone();
+            actix_req_head.version = req.version();
+            actix_req_head.headers = take(req.headers_mut()).into();
+            actix_req_head.uri = uri;
+            actix_req_head.peer_addr = peer_addr;
+
+            if let Some(expect) = expect {
+                (actix_req_head.extensions_mut()).insert(OriginalExpect(expect));
+            }
+            (actix_req_head.extensions_mut()).insert(event_body);
+
+            // See `with_extension`
+            for insert in &request_extensions {
+                insert(&mut actix_req_head.extensions_mut());
+            }
+            if let Some(form_params) = form_params {
+                (actix_req_head.extensions_mut()).insert(form_params);
+            }
+
+            // See `propagate_trace_id`
+            if propagate_trace_id && !actix_req_head.headers.contains_key("x-amzn-trace-id") {
+                let trace_id = (ctx.xray_trace_id.clone())
+                    .or_else(|| std::env::var("_X_AMZN_TRACE_ID").ok())
+                    .and_then(|trace_id| HeaderValue::from_str(&trace_id).ok());
+                if let Some(trace_id) = trace_id {
+                    (actix_req_head.headers)
+                        .insert(HeaderName::from_static("x-amzn-trace-id"), trace_id);
+                }
+            }
+
+            // See `echo_trace_id`
+            let echoed_trace_id = if echo_trace_id {
+                actix_req_head.headers.get("x-amzn-trace-id").cloned()
+            } else {
+                None
+            };
+
+            // See `honor_method_override`
+            if honor_method_override && actix_req_head.method == Method::POST {
+                if let Some(value) = actix_req_head.headers.get("x-http-method-override") {
+                    match Method::from_bytes(value.as_bytes()) {
+                        Ok(method) => {
+                            debug!("Overriding the method with {:?}", method);
+                            let original = replace(&mut actix_req_head.method, method);
+                            (actix_req_head.extensions_mut()).insert(OriginalMethod(original));
+                        }
+                        Err(_) => {
+                            debug!("Ignoring the invalid method override {:?}", value);
+                        }
+                    }
+                }
+            }
+
+            if let Some(&source) = req.extensions().get::<EventSource>() {
+                (actix_req_head.extensions_mut()).insert(source);
+            }
+
+            if let Some(base_path) = &detected_base_path {
+                (actix_req_head.extensions_mut()).insert(BasePath(base_path.clone()));
+            }
+
+            let cookies = Cookies::from_headers(actix_req_head.headers.get_all(&COOKIE));
+            (actix_req_head.extensions_mut()).insert(cookies);
+
+            (actix_req_head.extensions_mut()).insert(PathParameters(req.path_parameters()));
+
+            if let RequestContext::ApiGateway { authorizer, .. } = req.request_context() {
+                if !authorizer.is_empty() {
+                    (actix_req_head.extensions_mut()).insert(AuthorizerContext(authorizer));
+                }
+            }
+
+            let request_id = match req.request_context() {
+                RequestContext::ApiGateway { request_id, .. } if !request_id.is_empty() => {
+                    Some(request_id)
+                }
+                _ if !ctx.aws_request_id.is_empty() => Some(ctx.aws_request_id.clone()),
+                #[cfg(feature = "uuid")]
+                _ => Some(uuid::Uuid::new_v4().to_string()),
+                #[cfg(not(feature = "uuid"))]
+                _ => None,
+            };
+            if let Some(request_id) = request_id {
+                (actix_req_head.extensions_mut()).insert(RequestId(request_id));
+            }
+
+            // See `verify_body`
+            if let (Some(verify_body), Some(raw_body)) = (&mut verify_body, &raw_body) {
+                if let Err(resp) = verify_body(actix_req.head(), raw_body) {
+                    debug!("The request body was rejected by `verify_body`");
+                    return resp;
+                }
+            }
+
+            // See `map_request_body`
+            let mapped = match &mut map_request_body {
+                Some(map_request_body) => map_request_body(actix_req.head(), body),
+                None => Ok(body),
+            };
+            let user_resp = match mapped {
+                Ok(body) => {
+                    // The length may have been changed by `map_request_body`
+                    if event_body != EventBody::Absent || !body.is_empty() {
+                        (actix_req.headers_mut()).insert(CONTENT_LENGTH, body.len().into());
+                    }
+
+                    // Split the body into chunks (see `payload_chunk_size`)
+                    let chunk_size = payload_chunk_size.unwrap_or(body.len()).max(1);
+                    let mut start = 0;
+                    while start < body.len() {
+                        let end = (start + chunk_size).min(body.len());
+                        payload_sender.feed_data(body.slice(start, end));
+                        start = end;
+                    }
+
+                    // Mark the end of the body explicitly so that consumers
+                    // reading the payload to the end don't wait for more data
+                    payload_sender.feed_eof();
+
+                    // Call the inner handler. actix-web polls the handler's
+                    // future inside `call`, so it must be called in a task
+                    // context (some extractors such as
+                    // `actix_multipart::Multipart` panic otherwise).
+                    rt.block_on(futures::future::lazy(|| service.call(actix_req)))
+                        // Convert `S::Error` to `Error`
+                        .map_err(Into::into)
+                }
+                Err(e) => {
+                    debug!("`map_request_body` failed, treating it as a handler error");
+                    Err(e)
+                }
+            };
+
+            let mut actix_resp = user_resp
+                // Synchronously evaluate the response body
+                .and_then(|success_user_resp| {
+                    let mut actix_resp: Response<B> = success_user_resp.into();
+                    if actix_resp.error().is_some() {
+                        actix_resp.extensions_mut().insert(ErrorResponse);
+                    }
+
+                    // actix-web converts extractor errors to error responses
+                    // (see `json_error_bodies`)
+                    let message = actix_resp.error().filter(|_| json_error_bodies);
+                    if let Some(message) = message.and_then(extractor_error) {
+                        return Ok(json_error_response(actix_resp, message));
+                    }
+
+                    let resp_bytes = read_body(&mut rt, actix_resp.take_body(), body_read_timeout)
+                        .map(BytesMut::freeze);
+
+                    match resp_bytes {
+                        Ok(resp_bytes) => Ok(actix_resp.set_body(resp_bytes)),
+                        Err(e) => {
+                            // See `body_error_fallback`
+                            if let Some(fallback) = &mut body_error_fallback {
+                                debug!(
+                                    "Extracing the response failed ({:?}), using the fallback \
+                                     response",
+                                    e
+                                );
+                                let mut actix_resp = fallback(&e);
+                                let resp_bytes =
+                                    read_body(&mut rt, actix_resp.take_body(), body_read_timeout)?;
+                                return Ok(actix_resp.set_body(resp_bytes.freeze()));
+                            }
+
+                            debug!("Extracing the response failed, treating it as a handler error");
+                            Err(e)
+                        }
+                    }
+                })
+                // Construct a response for internal errors (if any)
+                .unwrap_or_else(|actix_err| {
+                    debug!(
+                        "Got a handler error ({:?}), generating an error response",
+                        actix_err
+                    );
+
+                    let mut actix_resp2 = actix_err.as_response_error().render_response();
+                    actix_resp2.extensions_mut().insert(ErrorResponse);
+
+                    // See `json_error_bodies`
+                    let message = Some(&actix_err).filter(|_| json_error_bodies);
+                    if let Some(message) = message.and_then(extractor_error) {
+                        return json_error_response(actix_resp2, message);
+                    }
+
+                    // Convert the body to `Bytes` from `Body`. However, this
+                    // operation is fallible. Should this fail, return an empty body,
+                    // ignoring the error.
+                    let resp_bytes = read_body(&mut rt, actix_resp2.take_body(), body_read_timeout)
+                        .map(BytesMut::freeze)
+                        .unwrap_or_else(|e| {
+                            warn!(
+                                "Failed to extract the body of the error response, ignoring: {:?}",
+                                e
+                            );
+
+                            Default::default()
+                        });
+
+                    actix_resp2.set_body(resp_bytes)
+                });
+
+            // See `conditional_get`
+            if let Some(if_none_match) = if_none_match {
+                let etag = actix_resp.headers().get(ETAG);
+                let matches = actix_resp.status().is_success()
+                    && (if_none_match.to_str().ok())
+                        .zip(etag.and_then(|etag| etag.to_str().ok()))
+                        .is_some_and(|(if_none_match, etag)| etag_matches(if_none_match, etag));
+                if matches {
+                    debug!("The entity tag matches `If-None-Match`, returning 304");
+                    *actix_resp.status_mut() = StatusCode::NOT_MODIFIED;
+                    actix_resp.headers_mut().remove(CONTENT_LENGTH);
+                    actix_resp = actix_resp.set_body(Bytes::new());
+                }
+            }
+
+            // Prepend the base path to `Location` (see `rewrite_redirect_locations`)
+            let redirect_base_path = mounted_base_path.or(detected_base_path.as_ref());
+            if let (true, Some(base_path)) = (rewrite_redirect_locations, redirect_base_path) {
+                let location = (actix_resp.headers().get(LOCATION))
+                    .and_then(|value| value.to_str().ok())
+                    .filter(|value| value.starts_with('/') && !value.starts_with("//"))
+                    .and_then(|value| {
+                        HeaderValue::from_str(&format!("{}{}", base_path, value)).ok()
+                    });
+                if let Some(location) = location {
+                    debug!(
+                        "Rewriting the `Location` header to {:?}",
+                        RedactedQuery(location.to_str().unwrap_or(""), &redacted_query_parameters)
+                    );
+                    actix_resp.headers_mut().insert(LOCATION, location);
+                }
+            }
+
+            // Let `to_lambda_response` know (see `binary_by_accept`)
+            if accepts_binary {
+                actix_resp.extensions_mut().insert(AcceptsBinary);
+            }
+
+            // See `echo_trace_id`
+            if let Some(trace_id) = echoed_trace_id {
+                if !actix_resp.headers().contains_key("x-amzn-trace-id") {
+                    (actix_resp.headers_mut())
+                        .insert(HeaderName::from_static("x-amzn-trace-id"), trace_id);
+                }
+            }
+
+            actix_resp
+        };
+
+        let mut lambda_http_handler = lambda_http_handler;
+        runner(&mut LambdaHttpService {
+            respond: &mut |input, ctx| {
+                // See `log_prefix` and `log_request_id`. This is also set
+                // by `run` for the messages emitted before this point.
+                log_context::set_prefix(&log_prefix, log_request_id, &ctx);
+
+                let actix_resp = match input {
+                    Ok(req) => lambda_http_handler(req, ctx),
+                    Err(actix_resp) => actix_resp,
                 };
 
-                // Then, copy the header
-                let mut resp = lambda_http::Response::new(resp_body);
-                *resp.status_mut() = actix_resp.status();
-                *resp.headers_mut() = actix_resp
-                    .headers()
-                    .iter()
-                    .map(|(k, v)| (k.clone(), v.clone()))
+                let mut actix_resp = validate_response(actix_resp, normalize_invalid_responses);
+                remove_hop_by_hop_headers(&mut actix_resp, &kept_hop_by_hop_headers);
+
+                // See `strip_response_header_prefix`
+                if strip_response_header_prefix {
+                    let prefix = stripped_response_header_prefix.as_str();
+                    let names: Vec<HeaderName> = (actix_resp.headers().keys())
+                        .filter(|name| name.as_str().starts_with(prefix))
+                        .cloned()
+                        .collect();
+                    for name in names {
+                        debug!("Removing the header {:?} from the response", name);
+                        actix_resp.headers_mut().remove(name);
+                    }
+                }
+
+                // See `error_content_type` and `error_body_template`
+                if actix_resp.extensions().get::<ErrorResponse>().is_some() {
+                    if let Some(template) = &error_body_template {
+                        let status = actix_resp.status();
+                        let body = template
+                            .replace("{status}", status.as_str())
+                            .replace("{reason}", status.canonical_reason().unwrap_or(""));
+                        actix_resp = actix_resp.set_body(Bytes::from(body));
+                    }
+                    if let Some(content_type) = &error_content_type {
+                        (actix_resp.headers_mut()).insert(CONTENT_TYPE, content_type.clone());
+                    }
+                }
+
+                // See `default_content_type`
+                if let Some(content_type) = &default_content_type {
+                    let has_body = match actix_resp.body() {
+                        ResponseBody::Body(bytes) => !bytes.is_empty(),
+                        ResponseBody::Other(_) => unreachable!(),
+                    };
+                    if has_body && !actix_resp.headers().contains_key(CONTENT_TYPE) {
+                        (actix_resp.headers_mut()).insert(CONTENT_TYPE, content_type.clone());
+                    }
+                }
+
+                // See `default_response_headers`
+                let missing_headers: Vec<_> = (default_response_headers.iter())
+                    .filter(|(name, _)| !actix_resp.headers().contains_key(name))
                     .collect();
+                for (name, value) in missing_headers {
+                    (actix_resp.headers_mut()).append(name.clone(), value.clone());
+                }
 
-                Ok(resp)
-            };
+                // See `date_header`
+                if date_header && !actix_resp.headers().contains_key(DATE) {
+                    let date = HttpDate::from(SystemTime::now()).to_string();
+                    if let Ok(date) = HeaderValue::from_str(&date) {
+                        actix_resp.headers_mut().insert(DATE, date);
+                    }
+                }
+
+                // See `map_response`
+                if let Some(map_response) = &mut map_response {
+                    map_response(&mut actix_resp);
+                }
 
-        lambda_http::lambda!(lambda_http_handler);
+                to_lambda_response(
+                    actix_resp,
+                    &mut binary_media_type_fn,
+                    &mut response_header_filter,
+                    append_charset,
+                    set_content_length,
+                    utf8_binary_as_text,
+                )
+            },
+        });
 
         Ok(())
     }
 }
 
-fn read_body(rt: &mut actix_rt::Runtime, body: impl MessageBody) -> Result<BytesMut, Error> {
-    rt.block_on(ResponseBody::Body(body).fold(BytesMut::new(), |mut x, y| {
+impl<F, R, S, B> LambdaHttpServer<F, R, S, B>
+where
+    F: FnMut() -> R,
+    R: IntoNewService<S>,
+    S: NewService<Config = ServerConfig, Request = Request>,
+    S::Error: Into<Error>,
+    S::Response: Into<Response<B>>,
+    B: MessageBody,
+{
+    /// Construct a `LambdaHttpServer` that retries the construction of the
+    /// application service up to `retries` times when it fails (e.g., because
+    /// of a transient network error while warming up a connection pool).
+    ///
+    /// Each attempt calls `app_factory` again and creates a new service from
+    /// the result, which is why `app_factory` must be `FnMut` unlike
+    /// [`new`](Self::new). If all attempts fail, `start` returns the error of
+    /// the last attempt. Since retries happen before polling for events, no
+    /// events are lost.
+    pub fn new_with_retries(app_factory: F, retries: usize) -> Self {
+        Self {
+            call_factory_mut: Some(|factory| factory()),
+            init_retries: retries,
+            ..Self::new(app_factory)
+        }
+    }
+}
+
+/// A [`Service`] that converts a `lambda_http::Request` to a request for the
+/// application service and converts the response back, applying the options
+/// of [`LambdaHttpServer`]. See [`LambdaHttpServer::run_service`].
+///
+/// The request is processed synchronously, so the returned future is always
+/// ready.
+pub struct LambdaHttpService<'a> {
+    respond: &'a mut RespondFn<'a>,
+}
+
+impl LambdaHttpService<'_> {
+    /// Process a request with the specified Lambda context. [`Service::call`]
+    /// uses the default context instead.
+    pub fn call_with_context(
+        &mut self,
+        req: lambda_http::Request,
+        ctx: lambda_runtime::Context,
+    ) -> Result<lambda_http::Response<LambdaBody>, HandlerError> {
+        self.respond(Ok(req), ctx)
+    }
+
+    /// Process a request, or finish an error response generated before a
+    /// request is constructed.
+    fn respond(
+        &mut self,
+        input: Result<lambda_http::Request, Response<Bytes>>,
+        ctx: lambda_runtime::Context,
+    ) -> Result<lambda_http::Response<LambdaBody>, HandlerError> {
+        (self.respond)(input, ctx)
+    }
+}
+
+impl Service for LambdaHttpService<'_> {
+    type Request = lambda_http::Request;
+    type Response = lambda_http::Response<LambdaBody>;
+    type Error = HandlerError;
+    type Future = futures::future::FutureResult<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self) -> futures::Poll<(), Self::Error> {
+        Ok(futures::Async::Ready(()))
+    }
+
+    fn call(&mut self, req: Self::Request) -> Self::Future {
+        self.call_with_context(req, lambda_runtime::Context::default())
+            .into()
+    }
+}
+
+/// Formats a header map with the values of the specified headers masked (see
+/// `redact_headers`).
+struct RedactedHeaders<'a>(&'a lambda_http::http::HeaderMap, &'a [HeaderName]);
+
+impl fmt::Debug for RedactedHeaders<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut map = f.debug_map();
+        for (name, value) in self.0.iter() {
+            if self.1.contains(name) {
+                map.entry(name, &"<redacted>");
+            } else {
+                map.entry(name, value);
+            }
+        }
+        map.finish()
+    }
+}
+
+/// Formats a URI (or its part) with the values of the specified query
+/// parameters masked (see `redact_query_parameters`).
+struct RedactedQuery<'a>(&'a str, &'a [String]);
+
+impl fmt::Debug for RedactedQuery<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (path, query) = match self.0.find('?') {
+            Some(i) => self.0.split_at(i + 1),
+            None => return fmt::Debug::fmt(self.0, f),
+        };
+        let mut out = path.to_owned();
+        for (i, pair) in query.split('&').enumerate() {
+            if i > 0 {
+                out.push('&');
+            }
+            let key = pair.split('=').next().unwrap_or_default();
+            if is_redacted_query_parameter(
+                &percent_decode(key.as_bytes()).decode_utf8_lossy(),
+                self.1,
+            ) {
+                write!(out, "{}=<redacted>", key)?;
+            } else {
+                out.push_str(pair);
+            }
+        }
+        fmt::Debug::fmt(&out, f)
+    }
+}
+
+/// Formats query string parameters with the values of the specified
+/// parameters masked (see `redact_query_parameters`).
+struct RedactedParams<'a>(&'a StrMap, &'a [String]);
+
+impl fmt::Debug for RedactedParams<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut map = f.debug_map();
+        for (key, _) in self.0.iter() {
+            if is_redacted_query_parameter(key, self.1) {
+                map.entry(&key, &"<redacted>");
+            } else {
+                map.entry(&key, &self.0.get_all(key).unwrap_or_default());
+            }
+        }
+        map.finish()
+    }
+}
+
+fn is_redacted_query_parameter(key: &str, redacted: &[String]) -> bool {
+    redacted.iter().any(|name| name.eq_ignore_ascii_case(key))
+}
+
+fn read_body(
+    rt: &mut actix_rt::Runtime,
+    body: impl MessageBody,
+    timeout: Option<Duration>,
+) -> Result<BytesMut, Error> {
+    let fut = ResponseBody::Body(body).fold(BytesMut::new(), |mut x, y| {
         x.extend_from_slice(&y);
         Ok::<_, Error>(x)
-    }))
+    });
+
+    // See `body_read_timeout`
+    if let Some(timeout) = timeout {
+        rt.block_on(Timeout::new(fut, timeout)).map_err(|e| {
+            if e.is_elapsed() {
+                warn!("Reading the response body timed out after {:?}", timeout);
+                ErrorInternalServerError("Reading the response body timed out")
+            } else if e.is_timer() {
+                ErrorInternalServerError("Timer error")
+            } else {
+                e.into_inner().unwrap()
+            }
+        })
+    } else {
+        rt.block_on(fut)
+    }
+}
+
+/// Construct `lambda_http::Response` from a response with a materialized body.
+fn to_lambda_response(
+    mut actix_resp: Response<Bytes>,
+    binary_media_type_fn: &mut dyn FnMut(&str) -> bool,
+    header_filter: &mut dyn FnMut(&HeaderName, &HeaderValue) -> bool,
+    append_charset: bool,
+    set_content_length: bool,
+    utf8_binary_as_text: Option<usize>,
+) -> Result<lambda_http::Response<LambdaBody>, HandlerError> {
+    let resp_body_bytes = match actix_resp.take_body() {
+        ResponseBody::Body(bytes) => bytes,
+        ResponseBody::Other(_) => unreachable!(),
+    };
+
+    // Clone the payload as a `Vec`
+    // (I couldn't find a copy-less way to do this)
+    let resp_body_vec = resp_body_bytes.to_vec();
+
+    let content_type = (actix_resp.head().headers())
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("");
+    let hints = actix_resp
+        .extensions()
+        .get::<LambdaResponseHints>()
+        .cloned();
+    let is_binary = match hints.as_ref().and_then(|hints| hints.binary) {
+        Some(binary) => binary,
+        None if actix_resp.extensions().get::<AcceptsBinary>().is_some() => true,
+        // See `utf8_binary_as_text`
+        None if utf8_binary_as_text.is_some_and(|max| resp_body_vec.len() <= max)
+            && std::str::from_utf8(&resp_body_vec).is_ok() =>
+        {
+            false
+        }
+        None => binary_media_type_fn(content_type),
+    };
+
+    debug!(
+        "Encoding the response body as {} for content type {:?}",
+        if is_binary { "binary" } else { "text" },
+        content_type
+    );
+
+    // See `append_charset`
+    if append_charset && !is_binary {
+        if let Some(value) = content_type_with_charset(content_type) {
+            debug!("Replacing the content type with {:?}", value);
+            actix_resp.headers_mut().insert(CONTENT_TYPE, value);
+        }
+    }
+
+    // The body may not match the `Content-Length` set by the application
+    // (see `set_content_length`)
+    let body_len = resp_body_vec.len();
+    let has_content_length = actix_resp.headers().contains_key(CONTENT_LENGTH);
+    if body_len > 0 && (has_content_length || set_content_length) {
+        (actix_resp.headers_mut()).insert(CONTENT_LENGTH, HeaderValue::from(body_len));
+    }
+
+    // An empty body is never base64-encoded regardless of `is_binary`
+    let resp_body = if resp_body_vec.is_empty() {
+        LambdaBody::Empty
+    } else if is_binary {
+        LambdaBody::Binary(resp_body_vec)
+    } else {
+        LambdaBody::Text(String::from_utf8(resp_body_vec)?)
+    };
+
+    // Then, copy the header (see `response_header_filter`)
+    let mut resp = lambda_http::Response::new(resp_body);
+    *resp.status_mut() = actix_resp.status();
+    *resp.headers_mut() = actix_resp
+        .headers()
+        .iter()
+        .filter(|&(k, v)| {
+            let keep = header_filter(k, v);
+            if !keep {
+                debug!("Removing the header {:?} from the response", k);
+            }
+            keep
+        })
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+
+    if let Some(hints) = hints {
+        resp.extensions_mut().insert(hints);
+    }
+
+    Ok(resp)
+}
+
+/// Decompress a request body (see `decompress_request_body`). Returns `None`
+/// if `encoding` is not supported. At most `limit` bytes are produced.
+fn decompress(body: &[u8], encoding: &str, limit: u64) -> Option<std::io::Result<Vec<u8>>> {
+    use flate2::read::{DeflateDecoder, MultiGzDecoder, ZlibDecoder};
+    use std::io::Read;
+
+    let read = |mut reader: Box<dyn Read + '_>| {
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out).map(|_| out)
+    };
+    Some(match encoding {
+        "gzip" | "x-gzip" => read(Box::new(MultiGzDecoder::new(body).take(limit))),
+        // `deflate` is supposed to be zlib-wrapped, but some clients send raw
+        // deflate data
+        "deflate" => read(Box::new(ZlibDecoder::new(body).take(limit)))
+            .or_else(|_| read(Box::new(DeflateDecoder::new(body).take(limit)))),
+        #[cfg(feature = "brotli")]
+        "br" => read(Box::new(
+            brotli_decompressor::Decompressor::new(body, 4096).take(limit),
+        )),
+        _ => return None,
+    })
+}
+
+/// Get the client's IP address from the request context (API Gateway) or the
+/// last entry of `X-Forwarded-For` (ALB).
+fn source_ip(req: &lambda_http::Request) -> Option<IpAddr> {
+    match req.request_context() {
+        RequestContext::ApiGateway { identity, .. } => identity.source_ip.parse().ok(),
+        RequestContext::Alb { .. } => (req.headers().get_all("x-forwarded-for"))
+            .iter()
+            .next_back()
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.rsplit(',').next())
+            .and_then(|ip| ip.trim().parse().ok()),
+    }
+}
+
+/// Format a value of the `Forwarded` header as a token or, if it includes
+/// characters not allowed in a token, a quoted string.
+fn forwarded_value(value: &str) -> String {
+    let is_tchar = |c: char| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c);
+    if !value.is_empty() && value.chars().all(is_tchar) {
+        value.to_owned()
+    } else {
+        let escaped = value.replace('\\', "\\\\").replace('"', "\\\"");
+        format!("\"{}\"", escaped)
+    }
+}
+
+/// A response extension indicating that the response was generated from an
+/// error (see `error_content_type`).
+struct ErrorResponse;
+
+/// A response extension indicating that the request's `Accept` header matched
+/// one of the media types specified by `binary_by_accept`.
+struct AcceptsBinary;
+
+/// A media type pattern (see `binary_media_types_glob`).
+struct MediaTypePattern {
+    ty: String,
+    subtype: String,
+}
+
+impl MediaTypePattern {
+    fn new(pattern: &str) -> Self {
+        let pattern = pattern.trim().to_ascii_lowercase();
+        let (ty, subtype) = pattern.split_once('/').unwrap_or((&pattern, ""));
+        Self {
+            ty: ty.to_owned(),
+            subtype: subtype.to_owned(),
+        }
+    }
+
+    /// Check if a media type without parameters matches the pattern.
+    fn matches(&self, essence: &str) -> bool {
+        let essence = essence.to_ascii_lowercase();
+        let (ty, subtype) = match essence.split_once('/') {
+            Some(x) => x,
+            None => return false,
+        };
+        if self.ty != "*" && self.ty != ty {
+            return false;
+        }
+        match self.subtype.strip_prefix('*') {
+            Some("") => true,
+            // e.g., `*+json`
+            Some(suffix) => subtype.len() > suffix.len() && subtype.ends_with(suffix),
+            None => self.subtype == subtype,
+        }
+    }
+}
+
+/// Check if the first media range in `accept` matches any of `types` (see
+/// `binary_by_accept`).
+fn accept_matches(accept: &str, types: &[String]) -> bool {
+    let first = accept.split(',').next().unwrap_or("");
+    let range = first.split(';').next().unwrap_or("").trim();
+    if range.is_empty() {
+        return false;
+    }
+    types.iter().any(|ty| {
+        if ty == "*/*" {
+            true
+        } else if let Some(prefix) = ty.strip_suffix("/*") {
+            range
+                .split('/')
+                .next()
+                .is_some_and(|top| top.eq_ignore_ascii_case(prefix))
+        } else {
+            range.eq_ignore_ascii_case(ty)
+        }
+    })
+}
+
+/// Append `; charset=utf-8` to a textual media type without a `charset`
+/// parameter (see `append_charset`). Returns `None` if it shouldn't be
+/// changed.
+fn content_type_with_charset(content_type: &str) -> Option<HeaderValue> {
+    let mime: mime::Mime = content_type.parse().ok()?;
+    let textual = mime.type_() == mime::TEXT
+        || (mime.type_() == mime::APPLICATION && mime.subtype() == mime::JSON);
+    if !textual || mime.get_param(mime::CHARSET).is_some() {
+        return None;
+    }
+    HeaderValue::from_str(&format!("{}; charset=utf-8", content_type)).ok()
+}
+
+/// Log a warning if the response is not valid as a final HTTP response. If
+/// `normalize` is `true`, remove the body of a response with a status code that
+/// doesn't allow one.
+fn validate_response(mut actix_resp: Response<Bytes>, normalize: bool) -> Response<Bytes> {
+    let status = actix_resp.status();
+    let has_body = match actix_resp.body() {
+        ResponseBody::Body(bytes) => !bytes.is_empty(),
+        ResponseBody::Other(_) => unreachable!(),
+    };
+
+    if status.is_informational() {
+        warn!(
+            "The response has an informational status code ({}), which is not \
+             valid as a final response",
+            status
+        );
+    } else if has_body && (status == StatusCode::NO_CONTENT || status == StatusCode::NOT_MODIFIED) {
+        warn!(
+            "The response has a non-empty body, which is not allowed for status code {}",
+            status
+        );
+        if normalize {
+            debug!("Removing the response body");
+            actix_resp = actix_resp.set_body(Bytes::new());
+        }
+    }
+
+    actix_resp
+}
+
+/// Remove hop-by-hop headers from a response, except for those in `kept`.
+fn remove_hop_by_hop_headers(actix_resp: &mut Response<Bytes>, kept: &[String]) {
+    const HOP_BY_HOP_HEADERS: &[&str] = &[
+        "connection",
+        "keep-alive",
+        "proxy-authenticate",
+        "proxy-authorization",
+        "te",
+        "trailer",
+        "transfer-encoding",
+        "upgrade",
+    ];
+
+    let headers = actix_resp.headers_mut();
+
+    // `Connection` may list additional hop-by-hop headers
+    let mut names: Vec<String> = (headers.get_all(&CONNECTION))
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect();
+    names.extend(HOP_BY_HOP_HEADERS.iter().map(|&name| name.to_owned()));
+
+    for name in names {
+        if !kept.contains(&name) && headers.contains_key(name.as_str()) {
+            debug!(
+                "Removing the hop-by-hop header {:?} from the response",
+                name
+            );
+            headers.remove(name.as_str());
+        }
+    }
+}
+
+/// Construct an empty response with the specified status code.
+fn status_response(status: StatusCode) -> Response<Bytes> {
+    let mut resp = Response::new(status).set_body(Bytes::new());
+    if status.is_client_error() || status.is_server_error() {
+        resp.extensions_mut().insert(ErrorResponse);
+    }
+    resp
+}
+
+/// Construct a `text/plain` response with the specified status code and
+/// message.
+fn error_response(status: StatusCode, message: &'static str) -> Response<Bytes> {
+    let mut resp = Response::new(status).set_body(Bytes::from_static(message.as_bytes()));
+    resp.extensions_mut().insert(ErrorResponse);
+    resp.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; charset=utf-8"),
+    );
+    resp
+}
+
+/// Get the message of an error if it originates from one of actix-web's
+/// extractors (see `json_error_bodies`).
+fn extractor_error(err: &Error) -> Option<String> {
+    None.or_else(|| err.as_error::<JsonPayloadError>().map(ToString::to_string))
+        .or_else(|| err.as_error::<UrlencodedError>().map(ToString::to_string))
+        .or_else(|| err.as_error::<QueryPayloadError>().map(ToString::to_string))
+        .or_else(|| err.as_error::<PathError>().map(ToString::to_string))
+        .or_else(|| err.as_error::<PayloadError>().map(ToString::to_string))
+}
+
+/// Replace the body of an error response with a JSON object containing
+/// `message` (see `json_error_bodies`).
+fn json_error_response<B>(mut actix_resp: Response<B>, message: String) -> Response<Bytes> {
+    debug!("Replacing the error response body with JSON");
+    let body = serde_json::json!({ "error": message }).to_string();
+    (actix_resp.headers_mut()).insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    actix_resp.set_body(Bytes::from(body))
+}
+
+/// Check if the entity tag `etag` matches `If-None-Match` using the weak
+/// comparison function.
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    fn opaque_tag(tag: &str) -> &str {
+        tag.trim().trim_start_matches("W/")
+    }
+    let etag = opaque_tag(etag);
+    if_none_match
+        .split(',')
+        .any(|tag| tag.trim() == "*" || opaque_tag(tag) == etag)
+}
+
+/// Reconstruct a path from an API Gateway resource path (e.g.,
+/// `/users/{id}/{proxy+}`) by substituting path parameters. Returns `None` if
+/// some of the parameters are missing.
+fn expand_resource_path(resource_path: &str, params: &StrMap) -> Option<String> {
+    let mut path = String::new();
+    for (i, segment) in resource_path.split('/').enumerate() {
+        if i > 0 {
+            path.push('/');
+        }
+
+        let name = match segment.strip_prefix('{').and_then(|x| x.strip_suffix('}')) {
+            Some(name) => name,
+            None => {
+                path.push_str(segment);
+                continue;
+            }
+        };
+
+        if let Some(name) = name.strip_suffix('+') {
+            // A greedy parameter spans multiple segments
+            for (k, value_segment) in params.get(name)?.split('/').enumerate() {
+                if k > 0 {
+                    path.push('/');
+                }
+                write!(
+                    path,
+                    "{}",
+                    utf8_percent_encode(value_segment, PATH_SEGMENT_ENCODE_SET)
+                )
+                .unwrap();
+            }
+        } else {
+            let value = params.get(name)?;
+            write!(
+                path,
+                "{}",
+                utf8_percent_encode(value, PATH_SEGMENT_ENCODE_SET)
+            )
+            .unwrap();
+        }
+    }
+    Some(path)
+}
+
+/// Find the suffix of `raw_path` that percent-decodes to `path`. `raw_path`
+/// may have extra leading segments such as a stage name.
+fn find_raw_path<'a>(raw_path: &'a str, path: &str) -> Option<&'a str> {
+    let mut rest = raw_path;
+    loop {
+        // `path` has non-ASCII characters percent-encoded by `from_event`
+        if percent_decode(rest.as_bytes())
+            .decode_utf8()
+            .is_ok_and(|x| utf8_percent_encode(&x, enc_set::NON_ASCII_ENCODE).to_string() == path)
+        {
+            return Some(rest);
+        }
+
+        // Remove the first segment
+        let i = rest.get(1..)?.find('/')? + 1;
+        rest = &rest[i..];
+    }
+}
+
+/// Check if `host` matches an `allowed_hosts` entry.
+fn host_matches(pattern: &str, host: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(domain) => {
+            let host = host.to_ascii_lowercase();
+            let suffix = format!(".{}", domain.to_ascii_lowercase());
+            host.len() > suffix.len() && host.ends_with(&suffix)
+        }
+        None => host.eq_ignore_ascii_case(pattern),
+    }
+}
+
+/// Remove `base_path` from `path`. Returns `None` if `path` is not under
+/// `base_path`.
+fn strip_base_path<'a>(path: &'a str, base_path: &str) -> Option<&'a str> {
+    if !path.starts_with(base_path) {
+        return None;
+    }
+    match &path[base_path.len()..] {
+        "" => Some("/"),
+        rest if rest.starts_with('/') => Some(rest),
+        _ => None,
+    }
 }
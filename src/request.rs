@@ -0,0 +1,425 @@
+//! Deserialization of ALB and API Gateway proxy events
+use lambda_http::{
+    http::{
+        header::{HeaderName, HeaderValue, HOST},
+        Uri,
+    },
+    Body as LambdaBody,
+};
+use lambda_runtime::error::LambdaErrorExt;
+use percent_encoding::{percent_decode, utf8_percent_encode, SIMPLE_ENCODE_SET};
+use serde_json::{Map, Value};
+use std::fmt;
+
+use crate::{enc_set::NON_ASCII_ENCODE, EventSource};
+
+/// The result of `from_event`.
+pub(crate) struct Event {
+    pub request: lambda_http::Request,
+    /// `true` if the event had no usable `Host` header and the fallback
+    /// authority was used to construct the request URI.
+    pub used_default_authority: bool,
+}
+
+/// The original percent-encoded request path (`rawPath` or
+/// `requestContext.path`), which is inserted to the extensions of a request
+/// returned by `from_event`.
+///
+/// Unlike the request URI, this may include a stage name or a base path.
+#[derive(Debug, Clone)]
+pub(crate) struct RawPath(pub String);
+
+/// The error type of `from_event`.
+pub(crate) enum EventError {
+    /// The event couldn't be deserialized.
+    Json(serde_json::Error),
+    /// The request URI is malformed.
+    MalformedUri(String),
+    /// The body is marked as base64-encoded but isn't a valid base64 string.
+    MalformedBody(base64::DecodeError),
+    /// The base64-encoded body would exceed `max_body_bytes` if decoded. The
+    /// value is the decoded size.
+    BodyTooLarge(usize),
+    /// The event isn't an HTTP event.
+    NotHttp(NotHttpEvent),
+}
+
+/// The error reported to the Lambda runtime when the event isn't an API
+/// Gateway or ALB event (e.g., a test event invoked from the console).
+#[derive(Debug)]
+pub(crate) struct NotHttpEvent {
+    missing_field: &'static str,
+}
+
+impl fmt::Display for NotHttpEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The event is not an API Gateway or ALB event (`{}` is missing or not a string)",
+            self.missing_field
+        )
+    }
+}
+
+impl std::error::Error for NotHttpEvent {}
+
+impl LambdaErrorExt for NotHttpEvent {
+    fn error_type(&self) -> &str {
+        "NotHttpEvent"
+    }
+}
+
+/// Determine the kind of the event source.
+pub(crate) fn event_source(event: &Value) -> EventSource {
+    let context = event.get("requestContext");
+    if context.and_then(|context| context.get("elb")).is_some() {
+        EventSource::Alb
+    } else if event.get("version").is_some()
+        || context.and_then(|context| context.get("http")).is_some()
+    {
+        // Only HTTP API events have a payload format version
+        EventSource::ApiGatewayHttp
+    } else {
+        EventSource::ApiGatewayRest
+    }
+}
+
+/// Convert an event to `lambda_http::Request`. `EventSource` is inserted to the
+/// extensions of the returned request.
+///
+/// `lambda_http` builds the request URI from the `Host` header and panics if
+/// the header is missing. To prevent this, `default_authority` is supplied as
+/// a temporary `Host` header in such cases. `lambda_http` also panics if the
+/// URI is malformed, which is reported as `EventError::MalformedUri`.
+/// `lambda_http` silently replaces a malformed base64-encoded body with an
+/// empty one, so such a body is decoded by this function instead, and a
+/// malformed one is reported as `EventError::MalformedBody`. If its decoded
+/// size exceeds `max_body_bytes`, `EventError::BodyTooLarge` is reported
+/// without decoding it. `lambda_http` rejects the whole event if any header
+/// is malformed, so such headers are removed or, if
+/// `encode_invalid_header_values` is `true` and only the value is malformed,
+/// percent-encoded. An event without `httpMethod` or `path` (after the
+/// conversion of a version 2.0 event) is reported as `EventError::NotHttp`.
+///
+/// `lambda_http` only understands the payload format version 1.0, so an HTTP
+/// API event with the version 2.0 is converted to the former first (see
+/// `normalize_v2_event`).
+pub(crate) fn from_event(
+    mut event: Value,
+    default_authority: &str,
+    encode_invalid_header_values: bool,
+    max_body_bytes: Option<usize>,
+) -> Result<Event, EventError> {
+    let mut used_default_authority = false;
+
+    if let Value::Object(event) = &mut event {
+        if event.get("version").and_then(Value::as_str) == Some("2.0") {
+            normalize_v2_event(event).map_err(EventError::NotHttp)?;
+        }
+    }
+
+    // Reject non-HTTP events with a descriptive error instead of the one from
+    // `lambda_http`
+    for &field in &["httpMethod", "path"] {
+        if !event.get(field).is_some_and(Value::is_string) {
+            return Err(EventError::NotHttp(NotHttpEvent {
+                missing_field: field,
+            }));
+        }
+    }
+
+    let source = event_source(&event);
+
+    let raw_path = (event.get("rawPath"))
+        .or_else(|| event.get("requestContext")?.get("path"))
+        .and_then(Value::as_str)
+        .map(str::to_owned);
+
+    // Decode a base64-encoded body by ourselves to detect a malformed one. The
+    // decoded body is put in the request below instead of letting
+    // `lambda_http` decode it again.
+    let mut decoded_body = None;
+    if event.get("isBase64Encoded") == Some(&Value::Bool(true)) {
+        if let Some(body) = event.get("body").and_then(Value::as_str) {
+            let padding = body.bytes().rev().take_while(|&b| b == b'=').count();
+            let decoded_len = (body.len() - padding) * 3 / 4;
+            if max_body_bytes.is_some_and(|max| decoded_len > max) {
+                return Err(EventError::BodyTooLarge(decoded_len));
+            }
+
+            decoded_body = Some(base64::decode(body).map_err(EventError::MalformedBody)?);
+        }
+    }
+
+    let cookies = (event.get("cookies").and_then(Value::as_array))
+        .map(|cookies| {
+            (cookies.iter())
+                .filter_map(Value::as_str)
+                .collect::<Vec<_>>()
+                .join("; ")
+        })
+        .filter(|cookies| !cookies.is_empty());
+
+    if let Value::Object(event) = &mut event {
+        if decoded_body.is_some() {
+            event.insert("body".to_owned(), Value::Null);
+            event.insert("isBase64Encoded".to_owned(), Value::Bool(false));
+        }
+
+        sanitize_headers(event, encode_invalid_header_values);
+
+        let host = find_header(event, "host").unwrap_or_else(|| {
+            used_default_authority = true;
+            default_authority.to_owned()
+        });
+
+        // Validate the URI in the same way as `lambda_http` constructs it
+        let scheme = (event.get("headers").and_then(Value::as_object))
+            .and_then(|headers| find_single_header(headers, "x-forwarded-proto"))
+            .unwrap_or("https");
+        let path = event.get("path").and_then(Value::as_str).unwrap_or("");
+
+        // `http::Uri` rejects non-ASCII characters, so encode them here. They
+        // are decoded back by actix's router.
+        let path = utf8_percent_encode(path, NON_ASCII_ENCODE).to_string();
+
+        let uri = format!("{}://{}{}", scheme, host, path);
+        if uri.parse::<Uri>().is_err() {
+            return Err(EventError::MalformedUri(uri));
+        }
+        if event.get("path").is_some_and(Value::is_string) {
+            event.insert("path".to_owned(), Value::String(path));
+        }
+
+        // `lambda_http` merges `headers` into `multiValueHeaders`, preserving
+        // repeated headers, but rejects `"multiValueHeaders": null`
+        if event
+            .get("multiValueHeaders")
+            .is_some_and(|x| !x.is_object())
+        {
+            event.remove("multiValueHeaders");
+        }
+
+        if !event.get("headers").is_some_and(Value::is_object) {
+            event.insert("headers".to_owned(), Value::Object(Map::new()));
+        }
+        let headers = event["headers"].as_object_mut().unwrap();
+
+        // Remove duplicates that differ only in case
+        headers.retain(|key, _| !key.eq_ignore_ascii_case("host"));
+        headers.insert("host".to_owned(), Value::String(host));
+
+        // HTTP API (payload format version 2.0) delivers cookies in a separate
+        // array instead of a `Cookie` header. Each entry is a `name=value` pair
+        // (the value may include `=`), so they can be joined as they are.
+        if let Some(cookies) = cookies {
+            let existing = (headers.iter_mut())
+                .find(|(key, _)| key.eq_ignore_ascii_case("cookie"))
+                .map(|(_, value)| value);
+            match existing {
+                Some(Value::String(existing)) if !existing.is_empty() => {
+                    existing.push_str("; ");
+                    existing.push_str(&cookies);
+                }
+                Some(existing) => *existing = Value::String(cookies),
+                None => {
+                    headers.insert("cookie".to_owned(), Value::String(cookies));
+                }
+            }
+        }
+    }
+
+    let mut request =
+        lambda_http::request::from_str(&event.to_string()).map_err(EventError::Json)?;
+
+    if used_default_authority {
+        request.headers_mut().remove(HOST);
+    }
+
+    request.extensions_mut().insert(source);
+
+    if let Some(body) = decoded_body {
+        *request.body_mut() = LambdaBody::Binary(body);
+    }
+
+    if let Some(raw_path) = raw_path {
+        request.extensions_mut().insert(RawPath(raw_path));
+    }
+
+    Ok(Event {
+        request,
+        used_default_authority,
+    })
+}
+
+/// Convert an HTTP API event with the payload format version 2.0 to the
+/// version 1.0 format in place.
+///
+/// - `httpMethod` is taken from `requestContext.http.method`.
+/// - `path` is the percent-decoded `rawPath`, like the `path` of the version
+///   1.0. `rawPath` is left intact and used by `use_raw_path`.
+/// - `multiValueQueryStringParameters` is parsed from `rawQueryString`
+///   because `queryStringParameters` has repeated parameters joined with
+///   commas.
+/// - `requestContext` is replaced with the API Gateway request context of the
+///   version 1.0. `resourcePath` is taken from the route key (e.g.,
+///   `/users/{proxy+}` from `ANY /users/{proxy+}`).
+///
+/// `headers` (where repeated headers are joined with commas), `cookies`,
+/// `pathParameters`, `stageVariables`, `body`, and `isBase64Encoded` can be
+/// used as they are.
+fn normalize_v2_event(event: &mut Map<String, Value>) -> Result<(), NotHttpEvent> {
+    let context = event.get("requestContext").cloned().unwrap_or(Value::Null);
+    let http = context.get("http").cloned().unwrap_or(Value::Null);
+    let string = |value: &Value, key: &str| {
+        Value::String(
+            value
+                .get(key)
+                .and_then(Value::as_str)
+                .unwrap_or("")
+                .to_owned(),
+        )
+    };
+
+    let method = (http.get("method").and_then(Value::as_str)).ok_or(NotHttpEvent {
+        missing_field: "requestContext.http.method",
+    })?;
+    let raw_path = (event.get("rawPath").and_then(Value::as_str)).ok_or(NotHttpEvent {
+        missing_field: "rawPath",
+    })?;
+    let path = percent_decode(raw_path.as_bytes())
+        .decode_utf8_lossy()
+        .into_owned();
+    let method = method.to_owned();
+
+    let query: Vec<(String, String)> = (event.get("rawQueryString").and_then(Value::as_str))
+        .and_then(|query| serde_urlencoded::from_str(query).ok())
+        .unwrap_or_default();
+    let mut multi_value_query = Map::new();
+    for (key, value) in query {
+        let values = (multi_value_query.entry(key)).or_insert_with(|| Value::Array(Vec::new()));
+        if let Value::Array(values) = values {
+            values.push(Value::String(value));
+        }
+    }
+
+    // e.g., `GET /users/{id}` or `$default`
+    let route_key = context
+        .get("routeKey")
+        .and_then(Value::as_str)
+        .unwrap_or("");
+    let resource_path = route_key.split_once(' ').map_or("", |(_, path)| path);
+
+    let mut new_context = Map::new();
+    for &key in &["accountId", "stage", "requestId", "apiId"] {
+        new_context.insert(key.to_owned(), string(&context, key));
+    }
+    new_context.insert("resourceId".to_owned(), Value::String(String::new()));
+    new_context.insert(
+        "resourcePath".to_owned(),
+        Value::String(resource_path.to_owned()),
+    );
+    new_context.insert("httpMethod".to_owned(), Value::String(method.clone()));
+    if let Some(authorizer) = context.get("authorizer").filter(|x| x.is_object()) {
+        new_context.insert("authorizer".to_owned(), authorizer.clone());
+    }
+    let mut identity = Map::new();
+    identity.insert("sourceIp".to_owned(), string(&http, "sourceIp"));
+    identity.insert("userAgent".to_owned(), string(&http, "userAgent"));
+    new_context.insert("identity".to_owned(), Value::Object(identity));
+
+    event.insert("httpMethod".to_owned(), Value::String(method));
+    event.insert("path".to_owned(), Value::String(path));
+    event.insert(
+        "multiValueQueryStringParameters".to_owned(),
+        Value::Object(multi_value_query),
+    );
+    if !event.contains_key("queryStringParameters") {
+        event.insert("queryStringParameters".to_owned(), Value::Null);
+    }
+    event.insert("requestContext".to_owned(), Value::Object(new_context));
+
+    Ok(())
+}
+
+/// Remove or percent-encode the headers in `headers` and `multiValueHeaders`
+/// that `lambda_http` would reject.
+fn sanitize_headers(event: &mut Map<String, Value>, encode_invalid_values: bool) {
+    if let Some(Value::Object(headers)) = event.get_mut("headers") {
+        headers.retain(|name, value| sanitize_header(name, value, encode_invalid_values));
+    }
+    if let Some(Value::Object(headers)) = event.get_mut("multiValueHeaders") {
+        headers.retain(|name, values| match values {
+            Value::Array(values) => {
+                values.retain_mut(|value| sanitize_header(name, value, encode_invalid_values));
+                true
+            }
+            _ => {
+                warn!("Removing the header {:?} with a malformed value list", name);
+                false
+            }
+        });
+    }
+}
+
+/// Validate a header. Returns `false` if the header should be removed.
+fn sanitize_header(name: &str, value: &mut Value, encode_invalid_value: bool) -> bool {
+    // `lambda_http` skips `multiValueHeaders` entries with an empty name
+    if name.is_empty() {
+        return true;
+    }
+    if HeaderName::from_bytes(name.as_bytes()).is_err() {
+        warn!("Removing the header with a malformed name {:?}", name);
+        return false;
+    }
+    let text = match value.as_str() {
+        Some(text) => text,
+        None => {
+            warn!("Removing the header {:?} with a non-string value", name);
+            return false;
+        }
+    };
+    if HeaderValue::from_str(text).is_ok() {
+        true
+    } else if encode_invalid_value {
+        debug!(
+            "Percent-encoding the malformed value of the header {:?}",
+            name
+        );
+        *value = Value::String(utf8_percent_encode(text, SIMPLE_ENCODE_SET).to_string());
+        true
+    } else {
+        warn!("Removing the header {:?} with a malformed value", name);
+        false
+    }
+}
+
+/// Find the first non-empty value of the specified header in `headers` or
+/// `multiValueHeaders`.
+fn find_header(event: &Map<String, Value>, name: &str) -> Option<String> {
+    let single = (event.get("headers").and_then(Value::as_object))
+        .into_iter()
+        .flatten()
+        .filter(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value);
+
+    let multi = (event.get("multiValueHeaders").and_then(Value::as_object))
+        .into_iter()
+        .flatten()
+        .filter(|(key, _)| key.eq_ignore_ascii_case(name))
+        .filter_map(|(_, values)| values.as_array())
+        .flatten();
+
+    single
+        .chain(multi)
+        .filter_map(Value::as_str)
+        .find(|value| !value.is_empty())
+        .map(str::to_owned)
+}
+
+/// Find the value of the specified header in a `headers` object.
+fn find_single_header<'a>(headers: &'a Map<String, Value>, name: &str) -> Option<&'a str> {
+    (headers.iter())
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .and_then(|(_, value)| value.as_str())
+}
@@ -0,0 +1,218 @@
+//! Serialization of ALB and API Gateway proxy responses
+use lambda_http::{
+    http::{header::SET_COOKIE, HeaderValue, StatusCode},
+    Body as LambdaBody,
+};
+use serde::{
+    ser::{Error as _, SerializeMap},
+    Serialize, Serializer,
+};
+
+use crate::{EventSource, LambdaResponseHints};
+
+/// The response object of a proxy integration. This is equivalent to
+/// `lambda_http`'s `LambdaResponse`, which isn't exported.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct LambdaResponse {
+    status_code: u16,
+    // ALB requires `statusDescription`, but API Gateway rejects a response
+    // including one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status_description: Option<String>,
+    // Not `HeaderMap` so that header names can have arbitrary cases (see
+    // `permute_set_cookie_case` and `apply_header_name_case`)
+    #[serde(serialize_with = "serialize_headers")]
+    headers: Vec<(String, HeaderValue)>,
+    #[serde(serialize_with = "serialize_multi_value_headers")]
+    multi_value_headers: Vec<(String, Vec<HeaderValue>)>,
+    // HTTP API (payload format version 2.0) ignores `multiValueHeaders` and
+    // takes `Set-Cookie` headers from this field instead
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    cookies: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    body: Option<String>,
+    is_base64_encoded: bool,
+}
+
+impl LambdaResponse {
+    /// Construct a `LambdaResponse`. `multi_value_headers` indicates whether
+    /// the event had `multiValueHeaders`.
+    pub(crate) fn from_response(
+        source: EventSource,
+        multi_value_headers: bool,
+        resp: lambda_http::Response<LambdaBody>,
+    ) -> Self {
+        let (mut parts, body) = resp.into_parts();
+        let hints = parts.extensions.remove::<LambdaResponseHints>();
+        let (is_base64_encoded, body) = match body {
+            LambdaBody::Empty => (false, None),
+            LambdaBody::Text(text) => (false, Some(text)),
+            LambdaBody::Binary(bytes) => (true, Some(base64::encode(&bytes))),
+        };
+        // HTTP API events have `multiValueHeaders` unless the payload format
+        // version is 2.0, in which case the response's `multiValueHeaders` is
+        // ignored
+        let payload_v2 = source == EventSource::ApiGatewayHttp && !multi_value_headers;
+        let cookies = if payload_v2 {
+            let cookies = (parts.headers.get_all(SET_COOKIE).iter())
+                .filter_map(|value| value.to_str().ok())
+                .map(str::to_owned)
+                .collect();
+            parts.headers.remove(SET_COOKIE);
+            cookies
+        } else {
+            Vec::new()
+        };
+        Self {
+            status_code: parts.status.as_u16(),
+            status_description: if source == EventSource::Alb {
+                Some(
+                    hints
+                        .and_then(|hints| hints.status_description)
+                        .unwrap_or_else(|| status_description(parts.status)),
+                )
+            } else {
+                None
+            },
+            headers: (parts.headers.keys())
+                .map(|key| {
+                    let value = if payload_v2 {
+                        // Repeated headers can only be delivered as a single
+                        // comma-separated value
+                        join_values(parts.headers.get_all(key).iter())
+                    } else {
+                        parts.headers[key].clone()
+                    };
+                    (key.as_str().to_owned(), value)
+                })
+                .collect(),
+            multi_value_headers: (parts.headers.keys())
+                .map(|key| {
+                    let values = parts.headers.get_all(key).iter().cloned().collect();
+                    (key.as_str().to_owned(), values)
+                })
+                .collect(),
+            cookies,
+            body,
+            is_base64_encoded,
+        }
+    }
+
+    /// Replace header names matching any of `names` case-insensitively with
+    /// the matching element of `names`.
+    pub(crate) fn apply_header_name_case(&mut self, names: &[String]) {
+        let keys = (self.headers.iter_mut().map(|(key, _)| key))
+            .chain(self.multi_value_headers.iter_mut().map(|(key, _)| key));
+        for key in keys {
+            if let Some(name) = names.iter().find(|name| name.eq_ignore_ascii_case(key)) {
+                key.clone_from(name);
+            }
+        }
+    }
+
+    /// Put each `Set-Cookie` header in `headers` under a distinct case
+    /// permutation of the name (`set-cookie`, `Set-cookie`, `sEt-cookie`, ...)
+    /// so that they survive ALB without multi-value headers. Headers beyond
+    /// `MAX_PERMUTED_SET_COOKIES` are dropped.
+    pub(crate) fn permute_set_cookie_case(&mut self) {
+        let values: Vec<_> = (self.multi_value_headers.iter())
+            .filter(|(key, _)| key.eq_ignore_ascii_case(SET_COOKIE.as_str()))
+            .flat_map(|(_, values)| values.iter().cloned())
+            .collect();
+        if values.len() <= 1 {
+            return;
+        }
+        if values.len() > MAX_PERMUTED_SET_COOKIES {
+            warn!(
+                "The response has too many `Set-Cookie` headers ({}), dropping all but \
+                 the first {}",
+                values.len(),
+                MAX_PERMUTED_SET_COOKIES
+            );
+        }
+
+        (self.headers).retain(|(key, _)| !key.eq_ignore_ascii_case(SET_COOKIE.as_str()));
+        for (i, value) in values
+            .into_iter()
+            .take(MAX_PERMUTED_SET_COOKIES)
+            .enumerate()
+        {
+            self.headers
+                .push((case_permutation(SET_COOKIE.as_str(), i), value));
+        }
+    }
+}
+
+/// The maximum number of `Set-Cookie` headers `permute_set_cookie_case` can
+/// handle, which is the number of case permutations of `set-cookie`.
+const MAX_PERMUTED_SET_COOKIES: usize = 1 << 9;
+
+/// Construct the `index`-th case permutation of `name`.
+fn case_permutation(name: &str, mut index: usize) -> String {
+    (name.chars())
+        .map(|c| {
+            if !c.is_ascii_alphabetic() {
+                return c;
+            }
+            let upper = index & 1 != 0;
+            index >>= 1;
+            if upper {
+                c.to_ascii_uppercase()
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+/// Join the values of a repeated header with `, `.
+fn join_values<'a>(values: impl Iterator<Item = &'a HeaderValue>) -> HeaderValue {
+    let mut joined = Vec::new();
+    for value in values {
+        if !joined.is_empty() {
+            joined.extend_from_slice(b", ");
+        }
+        joined.extend_from_slice(value.as_bytes());
+    }
+    // The values are valid header values, and so is the result
+    HeaderValue::from_bytes(&joined).unwrap()
+}
+
+/// Construct the default `statusDescription` (e.g., `200 OK`) for a status
+/// code. Only the code is included if it has no known reason phrase.
+fn status_description(status: StatusCode) -> String {
+    match status.canonical_reason() {
+        Some(reason) => format!("{} {}", status.as_u16(), reason),
+        None => status.as_u16().to_string(),
+    }
+}
+
+fn serialize_headers<S>(headers: &[(String, HeaderValue)], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let mut map = serializer.serialize_map(Some(headers.len()))?;
+    for (key, value) in headers {
+        let value = value.to_str().map_err(S::Error::custom)?;
+        map.serialize_entry(key, value)?;
+    }
+    map.end()
+}
+
+fn serialize_multi_value_headers<S>(
+    headers: &[(String, Vec<HeaderValue>)],
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let mut map = serializer.serialize_map(Some(headers.len()))?;
+    for (key, values) in headers {
+        let values = (values.iter())
+            .map(|value| value.to_str().map_err(S::Error::custom))
+            .collect::<Result<Vec<_>, _>>()?;
+        map.serialize_entry(key, &values)?;
+    }
+    map.end()
+}
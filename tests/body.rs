@@ -0,0 +1,507 @@
+//! Conversion of request bodies
+use actix_lambda_http::{EventBody, LambdaHttpServer, OriginalExpect};
+use actix_web::{error::ErrorBadRequest, web, web::Bytes, App, HttpRequest, HttpResponse};
+use futures::{Future, Stream};
+use serde_json::json;
+
+mod common;
+use common::{alb_event, body, body_str, call, invoke, rest_event};
+
+/// Construct an application that echoes the request body.
+macro_rules! echo_app {
+    () => {
+        || App::new().route("/", web::post().to(|body: web::Bytes| body))
+    };
+}
+
+#[test]
+fn base64_body() {
+    let payload = b"\x00\x01\xfe\xffbinary";
+    let mut event = alb_event("POST", "/");
+    event["body"] = json!(base64::encode(&payload[..]));
+    event["isBase64Encoded"] = json!(true);
+    let resp = invoke(
+        LambdaHttpServer::new(echo_app!()).binary_media_types(vec!["application/octet-stream"]),
+        event,
+    );
+    assert_eq!(resp["statusCode"], 200);
+    assert_eq!(body(&resp), &payload[..]);
+}
+
+#[test]
+fn base64_body_with_content_length() {
+    let payload = b"\x00\x01\xfe\xffbinary";
+    let encoded = base64::encode(&payload[..]);
+    let mut event = alb_event("POST", "/");
+    // The length of the encoded body
+    event["headers"]["content-length"] = json!(encoded.len().to_string());
+    event["headers"]["transfer-encoding"] = json!("chunked");
+    event["body"] = json!(encoded);
+    event["isBase64Encoded"] = json!(true);
+    let resp = invoke(
+        LambdaHttpServer::new(|| {
+            App::new().route(
+                "/",
+                web::post().to(|req: HttpRequest, body: web::Bytes| {
+                    format!(
+                        "{:?} {:?} {}",
+                        req.headers().get("content-length"),
+                        req.headers().get("transfer-encoding"),
+                        base64::encode(&body)
+                    )
+                }),
+            )
+        }),
+        event,
+    );
+    assert_eq!(resp["statusCode"], 200);
+    assert_eq!(
+        body_str(&resp),
+        format!(
+            "Some({:?}) None {}",
+            payload.len().to_string(),
+            base64::encode(&payload[..])
+        )
+    );
+}
+
+#[test]
+fn malformed_base64_body() {
+    let mut event = alb_event("POST", "/");
+    event["body"] = json!("not base64!");
+    event["isBase64Encoded"] = json!(true);
+    let resp = invoke(LambdaHttpServer::new(echo_app!()), event);
+    assert_eq!(resp["statusCode"], 400);
+}
+
+/// Send a body of `len` bytes with `max_request_body_bytes(8)` and return the
+/// status code.
+fn post_with_limit(len: usize, base64_encoded: bool) -> u64 {
+    let payload = vec![b'a'; len];
+    let mut event = alb_event("POST", "/");
+    if base64_encoded {
+        event["body"] = json!(base64::encode(&payload));
+        event["isBase64Encoded"] = json!(true);
+    } else {
+        event["body"] = json!(String::from_utf8(payload).unwrap());
+    }
+    let resp = invoke(
+        LambdaHttpServer::new(echo_app!()).max_request_body_bytes(8),
+        event,
+    );
+    resp["statusCode"].as_u64().unwrap()
+}
+
+#[test]
+fn max_request_body_bytes_text() {
+    assert_eq!(post_with_limit(8, false), 200);
+    assert_eq!(post_with_limit(9, false), 413);
+}
+
+#[test]
+fn max_request_body_bytes_binary() {
+    for len in 6..=8 {
+        assert_eq!(post_with_limit(len, true), 200);
+    }
+    for len in 9..=11 {
+        assert_eq!(post_with_limit(len, true), 413);
+    }
+}
+
+#[test]
+fn max_request_body_bytes_empty() {
+    assert_eq!(post_with_limit(0, false), 200);
+    assert_eq!(post_with_limit(0, true), 200);
+
+    let mut event = alb_event("POST", "/");
+    event["body"] = json!(null);
+    let resp = invoke(
+        LambdaHttpServer::new(echo_app!()).max_request_body_bytes(0),
+        event,
+    );
+    assert_eq!(resp["statusCode"], 200);
+}
+
+#[test]
+fn max_request_body_bytes_checked_before_decoding() {
+    // The body is malformed, but it's rejected because of its size first
+    let mut event = alb_event("POST", "/");
+    event["body"] = json!("!".repeat(100));
+    event["isBase64Encoded"] = json!(true);
+    let resp = invoke(
+        LambdaHttpServer::new(echo_app!()).max_request_body_bytes(8),
+        event,
+    );
+    assert_eq!(resp["statusCode"], 413);
+}
+
+/// Send `payload` with `payload_chunk_size(chunk_size)` and return the sizes
+/// of the chunks and the body received by the application.
+fn post_in_chunks(payload: &str, chunk_size: usize) -> String {
+    let mut event = alb_event("POST", "/");
+    event["body"] = json!(payload);
+    let resp = invoke(
+        LambdaHttpServer::new(|| {
+            App::new().route(
+                "/",
+                web::post().to_async(|payload: web::Payload| {
+                    payload
+                        .fold((Vec::new(), Vec::new()), |(mut sizes, mut body), chunk| {
+                            sizes.push(chunk.len().to_string());
+                            body.extend_from_slice(&chunk);
+                            Ok::<_, actix_web::error::PayloadError>((sizes, body))
+                        })
+                        .map(|(sizes, body)| {
+                            format!("{} {}", sizes.join(","), String::from_utf8(body).unwrap())
+                        })
+                }),
+            )
+        })
+        .payload_chunk_size(chunk_size),
+        event,
+    );
+    body_str(&resp)
+}
+
+#[test]
+fn payload_chunk_size_payload() {
+    assert_eq!(post_in_chunks("0123456789", 4), "4,4,2 0123456789");
+    assert_eq!(post_in_chunks("0123456789", 10), "10 0123456789");
+    assert_eq!(post_in_chunks("0123456789", 100), "10 0123456789");
+    // Zero is treated as one
+    assert_eq!(post_in_chunks("012", 0), "1,1,1 012");
+}
+
+#[test]
+fn payload_chunk_size_bytes() {
+    let payload: Vec<u8> = (0..=255).cycle().take(1000).collect();
+    let mut event = alb_event("POST", "/");
+    event["body"] = json!(base64::encode(&payload));
+    event["isBase64Encoded"] = json!(true);
+    let resp = invoke(
+        LambdaHttpServer::new(echo_app!())
+            .binary_media_types(vec!["application/octet-stream"])
+            .payload_chunk_size(7),
+        event,
+    );
+    assert_eq!(resp["statusCode"], 200);
+    assert_eq!(body(&resp), payload);
+}
+
+#[test]
+fn json_text_and_base64_bodies() {
+    #[derive(serde::Deserialize)]
+    struct Item {
+        name: String,
+        tags: Vec<String>,
+    }
+
+    let payload = r#"{"name": "café ☕", "tags": ["a", "b"]}"#;
+    let mut results = Vec::new();
+    for &base64_encoded in &[false, true] {
+        let mut event = alb_event("POST", "/");
+        event["headers"]["content-type"] = json!("application/json");
+        event["body"] = if base64_encoded {
+            json!(base64::encode(payload))
+        } else {
+            json!(payload)
+        };
+        event["isBase64Encoded"] = json!(base64_encoded);
+        let resp = invoke(
+            LambdaHttpServer::new(|| {
+                App::new().route(
+                    "/",
+                    web::post().to(|item: web::Json<Item>| {
+                        format!("{} {}", item.name, item.tags.join(","))
+                    }),
+                )
+            }),
+            event,
+        );
+        assert_eq!(resp["statusCode"], 200);
+        results.push(body_str(&resp));
+    }
+    assert_eq!(results[0], "café ☕ a,b");
+    assert_eq!(results[0], results[1]);
+}
+
+#[test]
+fn max_request_body_bytes_run_service() {
+    // Requests passed to `LambdaHttpService` directly are checked separately
+    // from events
+    for &(body, status) in &[("1234567", 200), ("12345678", 200), ("123456789", 413)] {
+        let req = lambda_http::http::Request::builder()
+            .method("POST")
+            .uri("https://example.com/")
+            .body(lambda_http::Body::from(body))
+            .unwrap();
+        let resp = call(
+            LambdaHttpServer::new(echo_app!()).max_request_body_bytes(8),
+            req,
+        );
+        assert_eq!(resp.status(), status, "{}", body);
+    }
+}
+
+#[test]
+fn max_request_body_bytes_multibyte() {
+    // The limit is in bytes, not characters
+    for &(body, status) in &[("ああ", 200), ("あああ", 413)] {
+        let mut event = alb_event("POST", "/");
+        event["body"] = json!(body);
+        let resp = invoke(
+            LambdaHttpServer::new(echo_app!()).max_request_body_bytes(8),
+            event,
+        );
+        assert_eq!(resp["statusCode"], status, "{}", body);
+    }
+}
+
+/// Send `body` with the `Content-Length` header `content_length` and return
+/// the `Content-Length` and the body seen by the application.
+fn post_with_content_length(body: serde_json::Value, content_length: Option<&str>) -> String {
+    let mut event = rest_event("POST", "/");
+    if let Some(content_length) = content_length {
+        event["headers"]["content-length"] = json!(content_length);
+    }
+    event["body"] = body;
+    let resp = invoke(
+        LambdaHttpServer::new(|| {
+            App::new().route(
+                "/",
+                web::post().to(|req: HttpRequest, body: web::Bytes| {
+                    format!(
+                        "{:?} {:?}",
+                        req.headers().get("content-length"),
+                        std::str::from_utf8(&body).unwrap()
+                    )
+                }),
+            )
+        }),
+        event,
+    );
+    assert_eq!(resp["statusCode"], 200);
+    body_str(&resp)
+}
+
+#[test]
+fn empty_body_with_content_length() {
+    assert_eq!(
+        post_with_content_length(json!(null), Some("10")),
+        r#"None """#
+    );
+    assert_eq!(
+        post_with_content_length(json!(""), Some("10")),
+        r#"Some("0") """#
+    );
+}
+
+#[test]
+fn content_length_set() {
+    assert_eq!(
+        post_with_content_length(json!("hello"), None),
+        r#"Some("5") "hello""#
+    );
+    assert_eq!(post_with_content_length(json!(""), None), r#"Some("0") """#);
+    assert_eq!(post_with_content_length(json!(null), None), r#"None """#);
+}
+
+#[test]
+fn content_length_corrected() {
+    assert_eq!(
+        post_with_content_length(json!("hello"), Some("10")),
+        r#"Some("5") "hello""#
+    );
+    assert_eq!(
+        post_with_content_length(json!("hello"), Some("bogus")),
+        r#"Some("5") "hello""#
+    );
+}
+
+#[test]
+fn expect_header_removed() {
+    let mut event = rest_event("POST", "/");
+    event["headers"]["expect"] = json!("100-continue");
+    event["body"] = json!("full body");
+    let resp = invoke(
+        LambdaHttpServer::new(|| {
+            App::new().route(
+                "/",
+                web::post().to(|req: HttpRequest, body: web::Bytes| {
+                    format!(
+                        "{:?} {:?} {:?}",
+                        req.headers().get("expect"),
+                        req.extensions().get::<OriginalExpect>(),
+                        std::str::from_utf8(&body).unwrap()
+                    )
+                }),
+            )
+        }),
+        event,
+    );
+    assert_eq!(resp["statusCode"], 200);
+    assert_eq!(
+        body_str(&resp),
+        r#"None Some(OriginalExpect("100-continue")) "full body""#
+    );
+}
+
+/// Send `event` to an application that reads the whole `web::Payload` and
+/// responds with the number of bytes read.
+fn drain_payload(event: serde_json::Value) -> String {
+    let resp = invoke(
+        LambdaHttpServer::new(|| {
+            App::new().route(
+                "/",
+                web::post().to_async(|payload: web::Payload| {
+                    payload
+                        .fold(0, |len, chunk| {
+                            Ok::<_, actix_web::error::PayloadError>(len + chunk.len())
+                        })
+                        .map(|len| len.to_string())
+                }),
+            )
+        }),
+        event,
+    );
+    assert_eq!(resp["statusCode"], 200);
+    body_str(&resp)
+}
+
+#[test]
+fn payload_drained() {
+    // Empty
+    let mut event = rest_event("POST", "/");
+    event["body"] = json!(null);
+    assert_eq!(drain_payload(event), "0");
+
+    // Text
+    let mut event = rest_event("POST", "/");
+    event["body"] = json!("hello");
+    assert_eq!(drain_payload(event), "5");
+
+    // Binary
+    let mut event = rest_event("POST", "/");
+    event["body"] = json!(base64::encode(b"\x00\x01\xfe\xff"));
+    event["isBase64Encoded"] = json!(true);
+    assert_eq!(drain_payload(event), "4");
+}
+
+/// Post `body` with `X-Signature: {signature}` to an application that echoes
+/// the body. `verify_body` checks the signature, which is the sum of the
+/// bytes of the body.
+fn post_signed(body: &str, signature: &str) -> serde_json::Value {
+    let mut event = alb_event("POST", "/");
+    event["headers"]["x-signature"] = json!(signature);
+    event["body"] = json!(body);
+    invoke(
+        LambdaHttpServer::new(echo_app!()).verify_body(|head, body| {
+            let expected = body.iter().map(|&b| u32::from(b)).sum::<u32>().to_string();
+            if head
+                .headers
+                .get("x-signature")
+                .map(|value| value.as_bytes())
+                == Some(expected.as_bytes())
+            {
+                Ok(())
+            } else {
+                Err(HttpResponse::Unauthorized()
+                    .finish()
+                    .set_body(Bytes::from_static(b"bad signature")))
+            }
+        }),
+        event,
+    )
+}
+
+#[test]
+fn verify_body_valid() {
+    // 'a' + 'b' + 'c' = 97 + 98 + 99
+    let resp = post_signed("abc", "294");
+    assert_eq!(resp["statusCode"], 200);
+    assert_eq!(body_str(&resp), "abc");
+}
+
+#[test]
+fn verify_body_invalid() {
+    let resp = post_signed("abc", "295");
+    assert_eq!(resp["statusCode"], 401);
+    assert_eq!(body_str(&resp), "bad signature");
+}
+
+/// Post `body` to an application that responds with the `Content-Length`
+/// header and the body it received. `map_request_body` unwraps the envelope
+/// `{"data": "..."}` and rejects bodies without one.
+fn post_enveloped(body: &str) -> serde_json::Value {
+    let mut event = alb_event("POST", "/");
+    event["body"] = json!(body);
+    invoke(
+        LambdaHttpServer::new(|| {
+            App::new().route(
+                "/",
+                web::post().to(|req: HttpRequest, body: String| {
+                    format!("{:?} {}", req.headers().get("content-length"), body)
+                }),
+            )
+        })
+        .map_request_body(|_, body| {
+            let envelope: serde_json::Value =
+                serde_json::from_slice(&body).map_err(ErrorBadRequest)?;
+            match envelope["data"].as_str() {
+                Some(data) => Ok(Bytes::from(data)),
+                None => Err(ErrorBadRequest("missing envelope")),
+            }
+        }),
+        event,
+    )
+}
+
+#[test]
+fn map_request_body_rewrite() {
+    let resp = post_enveloped(r#"{"data": "hello"}"#);
+    assert_eq!(resp["statusCode"], 200);
+    assert_eq!(body_str(&resp), r#"Some("5") hello"#);
+}
+
+#[test]
+fn map_request_body_reject() {
+    let resp = post_enveloped(r#"{"payload": "hello"}"#);
+    assert_eq!(resp["statusCode"], 400);
+    assert_eq!(body_str(&resp), "missing envelope");
+
+    let resp = post_enveloped("hello");
+    assert_eq!(resp["statusCode"], 400);
+}
+
+/// Send a `{method}` request with `body` to an application that responds with
+/// the `EventBody` and the `Content-Length` header it received.
+fn event_body(method: &str, body: serde_json::Value) -> String {
+    let mut event = rest_event(method, "/");
+    event["body"] = body;
+    let resp = invoke(
+        LambdaHttpServer::new(|| {
+            App::new().default_service(web::to(|req: HttpRequest| {
+                format!(
+                    "{:?} {:?}",
+                    req.extensions().get::<EventBody>(),
+                    req.headers().get("content-length")
+                )
+            }))
+        }),
+        event,
+    );
+    assert_eq!(resp["statusCode"], 200);
+    body_str(&resp)
+}
+
+#[test]
+fn event_body_absent() {
+    assert_eq!(event_body("GET", json!(null)), "Some(Absent) None");
+    assert_eq!(event_body("POST", json!(null)), "Some(Absent) None");
+}
+
+#[test]
+fn event_body_empty_text() {
+    assert_eq!(event_body("POST", json!("")), r#"Some(Text) Some("0")"#);
+    assert_eq!(event_body("GET", json!("")), r#"Some(Text) Some("0")"#);
+}
@@ -0,0 +1,156 @@
+//! Helpers shared by the integration tests
+#![allow(dead_code)]
+use actix_http::{Request, Response};
+use actix_lambda_http::LambdaHttpServer;
+use actix_server_config::ServerConfig;
+use actix_service::{IntoNewService, NewService};
+use actix_web::{dev::MessageBody, Error};
+use serde_json::{json, Value};
+use std::fmt::Debug;
+
+/// Construct an ALB event without `multiValueHeaders`.
+pub fn alb_event(method: &str, path: &str) -> Value {
+    json!({
+        "httpMethod": method,
+        "path": path,
+        "headers": {
+            "host": "example.com",
+            "x-forwarded-for": "192.0.2.1",
+            "x-forwarded-port": "443",
+            "x-forwarded-proto": "https"
+        },
+        "queryStringParameters": {},
+        "requestContext": {
+            "elb": {
+                "targetGroupArn": "arn:aws:elasticloadbalancing:us-east-1:123456789012:targetgroup/lambda/0123456789abcdef"
+            }
+        },
+        "body": "",
+        "isBase64Encoded": false
+    })
+}
+
+/// Construct an API Gateway REST API event for the stage `prod` accessed
+/// through the default endpoint.
+pub fn rest_event(method: &str, path: &str) -> Value {
+    json!({
+        "resource": "/{proxy+}",
+        "path": path,
+        "httpMethod": method,
+        "headers": {
+            "host": "abcdef1234.execute-api.us-east-1.amazonaws.com",
+            "x-forwarded-proto": "https"
+        },
+        "multiValueHeaders": {},
+        "queryStringParameters": null,
+        "multiValueQueryStringParameters": null,
+        "pathParameters": { "proxy": path.trim_start_matches('/') },
+        "stageVariables": null,
+        "requestContext": {
+            "accountId": "123456789012",
+            "resourceId": "abcdef",
+            "stage": "prod",
+            "requestId": "c6af9ac6-7b61-11e6-9a41-93e8deadbeef",
+            "resourcePath": "/{proxy+}",
+            "httpMethod": method,
+            "path": format!("/prod{}", path),
+            "apiId": "abcdef1234",
+            "identity": { "sourceIp": "192.0.2.1", "userAgent": "curl/7.64.1" }
+        },
+        "body": null,
+        "isBase64Encoded": false
+    })
+}
+
+/// Construct an API Gateway HTTP API event (payload format version 2.0) for
+/// the `$default` stage.
+pub fn http_v2_event(method: &str, raw_path: &str) -> Value {
+    json!({
+        "version": "2.0",
+        "routeKey": "$default",
+        "rawPath": raw_path,
+        "rawQueryString": "",
+        "headers": {
+            "host": "abcdef1234.execute-api.us-east-1.amazonaws.com",
+            "user-agent": "curl/7.64.1",
+            "x-forwarded-for": "192.0.2.1",
+            "x-forwarded-port": "443",
+            "x-forwarded-proto": "https"
+        },
+        "requestContext": {
+            "accountId": "123456789012",
+            "apiId": "abcdef1234",
+            "domainName": "abcdef1234.execute-api.us-east-1.amazonaws.com",
+            "domainPrefix": "abcdef1234",
+            "http": {
+                "method": method,
+                "path": raw_path,
+                "protocol": "HTTP/1.1",
+                "sourceIp": "192.0.2.1",
+                "userAgent": "curl/7.64.1"
+            },
+            "requestId": "JKJaXmPLvHcESHA=",
+            "routeKey": "$default",
+            "stage": "$default",
+            "time": "10/Mar/2020:05:16:23 +0000",
+            "timeEpoch": 1583817383220u64
+        },
+        "isBase64Encoded": false
+    })
+}
+
+/// Process `event` with `server` and return the Lambda response.
+pub fn invoke<F, R, S, B>(server: LambdaHttpServer<F, R, S, B>, event: Value) -> Value
+where
+    F: FnOnce() -> R,
+    R: IntoNewService<S>,
+    S: NewService<Config = ServerConfig, Request = Request>,
+    S::Error: Into<Error>,
+    S::Response: Into<Response<B>>,
+    S::InitError: Debug,
+    B: MessageBody,
+{
+    let mut output = Vec::new();
+    server
+        .run_local(event.to_string().as_bytes(), &mut output)
+        .unwrap();
+    serde_json::from_slice(&output).unwrap()
+}
+
+/// Process `req` with `server`, bypassing the conversion from an event.
+pub fn call<F, R, S, B>(
+    server: LambdaHttpServer<F, R, S, B>,
+    req: lambda_http::Request,
+) -> lambda_http::Response<lambda_http::Body>
+where
+    F: FnOnce() -> R,
+    R: IntoNewService<S>,
+    S: NewService<Config = ServerConfig, Request = Request>,
+    S::Error: Into<Error>,
+    S::Response: Into<Response<B>>,
+    S::InitError: Debug,
+    B: MessageBody,
+{
+    let mut resp = None;
+    server
+        .run_service(|service| {
+            resp = Some(service.call_with_context(req, Default::default()).unwrap())
+        })
+        .unwrap();
+    resp.unwrap()
+}
+
+/// Get the body of a Lambda response, decoding it if it's base64-encoded.
+pub fn body(resp: &Value) -> Vec<u8> {
+    let body = resp["body"].as_str().unwrap_or("");
+    if resp["isBase64Encoded"] == true {
+        base64::decode(body).unwrap()
+    } else {
+        body.as_bytes().to_vec()
+    }
+}
+
+/// Get the body of a Lambda response as a string.
+pub fn body_str(resp: &Value) -> String {
+    String::from_utf8(body(resp)).unwrap()
+}
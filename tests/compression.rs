@@ -0,0 +1,106 @@
+//! Decompression of request bodies (see `decompress_request_body`)
+use actix_lambda_http::LambdaHttpServer;
+use actix_web::{web, App, HttpRequest};
+use flate2::{write::GzEncoder, Compression};
+use serde_json::{json, Value};
+use std::io::Write;
+
+mod common;
+use common::{alb_event, body_str, invoke};
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+/// Send `body` with `Content-Encoding: {encoding}` and
+/// `decompress_request_body(true)`. The application responds with the
+/// `Content-Encoding` header and the body it received.
+fn post_compressed(body: &[u8], encoding: &str) -> Value {
+    post_compressed_with_limit(body, encoding, usize::MAX)
+}
+
+/// Like `post_compressed`, but with `max_request_body_bytes(max)`.
+fn post_compressed_with_limit(body: &[u8], encoding: &str, max: usize) -> Value {
+    let mut event = alb_event("POST", "/");
+    event["headers"]["content-encoding"] = json!(encoding);
+    event["body"] = json!(base64::encode(body));
+    event["isBase64Encoded"] = json!(true);
+
+    let server = LambdaHttpServer::new(|| {
+        App::new().route(
+            "/",
+            web::post().to(|req: HttpRequest, body: web::Bytes| {
+                format!(
+                    "{:?} {}",
+                    req.headers().get("content-encoding"),
+                    String::from_utf8_lossy(&body)
+                )
+            }),
+        )
+    })
+    .decompress_request_body(true)
+    .max_request_body_bytes(max);
+    invoke(server, event)
+}
+
+#[test]
+fn gzip_body() {
+    let resp = post_compressed(&gzip(b"hello, world"), "gzip");
+    assert_eq!(resp["statusCode"], 200);
+    assert_eq!(body_str(&resp), "None hello, world");
+}
+
+#[test]
+fn corrupted_gzip_body() {
+    let mut body = gzip(b"hello, world");
+    body.truncate(body.len() / 2);
+    let resp = post_compressed(&body, "gzip");
+    assert_eq!(resp["statusCode"], 400);
+
+    let resp = post_compressed(b"not gzip", "gzip");
+    assert_eq!(resp["statusCode"], 400);
+}
+
+#[test]
+fn gzip_body_limit() {
+    // The limit applies to the inflated body
+    let body = gzip(&[b'a'; 1000]);
+    assert!(body.len() < 100);
+    let resp = post_compressed_with_limit(&body, "gzip", 1000);
+    assert_eq!(resp["statusCode"], 200);
+    assert_eq!(body_str(&resp), format!("None {}", "a".repeat(1000)));
+
+    let resp = post_compressed_with_limit(&body, "gzip", 999);
+    assert_eq!(resp["statusCode"], 413);
+}
+
+#[cfg(feature = "brotli")]
+fn brotli(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    brotli::BrotliCompress(&mut &data[..], &mut output, &Default::default()).unwrap();
+    output
+}
+
+#[cfg(feature = "brotli")]
+#[test]
+fn brotli_body() {
+    let resp = post_compressed(&brotli(b"hello, world"), "br");
+    assert_eq!(resp["statusCode"], 200);
+    assert_eq!(body_str(&resp), "None hello, world");
+}
+
+#[cfg(feature = "brotli")]
+#[test]
+fn brotli_body_limit() {
+    // The limit applies to the inflated body
+    let body = brotli(&[b'a'; 1000]);
+    assert!(body.len() < 100);
+    let resp = post_compressed_with_limit(&body, "br", 1000);
+    assert_eq!(resp["statusCode"], 200);
+    assert_eq!(body_str(&resp), format!("None {}", "a".repeat(1000)));
+
+    let resp = post_compressed_with_limit(&body, "br", 999);
+    assert_eq!(resp["statusCode"], 413);
+}
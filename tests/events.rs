@@ -0,0 +1,196 @@
+//! Conversion of events of each event source
+use actix_lambda_http::{Cookies, EventSource, LambdaHttpServer, LocalError};
+use actix_web::{web, App, HttpMessage, HttpRequest};
+use lambda_runtime::error::{HandlerError, LambdaErrorExt};
+use serde_json::json;
+
+mod common;
+use common::{alb_event, body_str, http_v2_event, invoke, rest_event};
+
+#[test]
+fn http_v2() {
+    let mut event = http_v2_event("POST", "/users/42");
+    event["rawQueryString"] = json!("tag=a&tag=b");
+    event["body"] = json!("hello");
+    let resp = invoke(
+        LambdaHttpServer::new(|| {
+            App::new().route(
+                "/users/{id}",
+                web::post().to(|req: HttpRequest, body: String| {
+                    format!(
+                        "{} {} {} {}",
+                        req.method(),
+                        req.path(),
+                        req.query_string(),
+                        body
+                    )
+                }),
+            )
+        }),
+        event,
+    );
+    assert_eq!(resp["statusCode"], 200);
+    assert_eq!(body_str(&resp), "POST /users/42 tag=a&tag=b hello");
+}
+
+#[test]
+fn http_v2_cookies() {
+    let mut event = http_v2_event("GET", "/");
+    // The value of a cookie may include `=`
+    event["cookies"] = json!(["session=abc=def", "theme=dark"]);
+    let resp = invoke(
+        LambdaHttpServer::new(|| {
+            App::new().route(
+                "/",
+                web::get().to(|req: HttpRequest| {
+                    let session = req.cookie("session").unwrap();
+                    let theme = req.cookie("theme").unwrap();
+                    format!("{} {}", session.value(), theme.value())
+                }),
+            )
+        }),
+        event,
+    );
+    assert_eq!(body_str(&resp), "abc=def dark");
+}
+
+#[test]
+fn http_v2_cookies_with_cookie_header() {
+    let mut event = http_v2_event("GET", "/");
+    event["headers"]["cookie"] = json!("a=1");
+    event["cookies"] = json!(["b=2"]);
+    let resp = invoke(
+        LambdaHttpServer::new(|| {
+            App::new().route(
+                "/",
+                web::get().to(|req: HttpRequest| {
+                    let cookies = req.cookies().unwrap();
+                    let mut cookies: Vec<_> = cookies.iter().map(|c| c.to_string()).collect();
+                    cookies.sort();
+                    cookies.join(" ")
+                }),
+            )
+        }),
+        event,
+    );
+    assert_eq!(body_str(&resp), "a=1 b=2");
+}
+
+/// Format the `Cookies` extension of a request.
+fn format_cookies(req: HttpRequest) -> String {
+    let extensions = req.extensions();
+    let cookies = extensions.get::<Cookies>().unwrap();
+    let pairs: Vec<String> = (cookies.iter())
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect();
+    format!("{:?} {}", cookies.get("session"), pairs.join(","))
+}
+
+#[test]
+fn cookies_from_http_v2_array() {
+    let mut event = http_v2_event("GET", "/");
+    event["cookies"] = json!(["session=abc", "theme=dark"]);
+    let resp = invoke(
+        LambdaHttpServer::new(|| App::new().route("/", web::get().to(format_cookies))),
+        event,
+    );
+    assert_eq!(body_str(&resp), r#"Some("abc") session=abc,theme=dark"#);
+}
+
+#[test]
+fn cookies_from_cookie_header() {
+    let mut event = rest_event("GET", "/");
+    event["headers"]["cookie"] = json!("session=abc; theme=dark");
+    let resp = invoke(
+        LambdaHttpServer::new(|| App::new().route("/", web::get().to(format_cookies))),
+        event,
+    );
+    assert_eq!(body_str(&resp), r#"Some("abc") session=abc,theme=dark"#);
+}
+
+#[test]
+fn no_cookies() {
+    let resp = invoke(
+        LambdaHttpServer::new(|| App::new().route("/", web::get().to(format_cookies))),
+        rest_event("GET", "/"),
+    );
+    assert_eq!(body_str(&resp), "None ");
+}
+
+/// Process `event` and return the error reported to the Lambda runtime.
+fn reject(event: serde_json::Value) -> HandlerError {
+    let mut output = Vec::new();
+    let result = LambdaHttpServer::new(|| App::new().route("/", web::get().to(|| "ok")))
+        .run_local(event.to_string().as_bytes(), &mut output);
+    match result {
+        Err(LocalError::Handler(e)) => e,
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn non_http_event() {
+    // A test event of the Lambda console
+    let e = reject(json!({ "key1": "value1", "key2": "value2" }));
+    assert_eq!(e.error_type(), "NotHttpEvent");
+    assert!(e.to_string().contains("`httpMethod`"), "{}", e);
+
+    let e = reject(json!({ "httpMethod": "GET" }));
+    assert!(e.to_string().contains("`path`"), "{}", e);
+}
+
+#[test]
+fn non_http_v2_event() {
+    let mut event = http_v2_event("GET", "/");
+    event["requestContext"]
+        .as_object_mut()
+        .unwrap()
+        .remove("http");
+    let e = reject(event);
+    assert!(
+        e.to_string().contains("`requestContext.http.method`"),
+        "{}",
+        e
+    );
+}
+
+/// Process `event` and return the `EventSource` seen by the application.
+fn event_source(event: serde_json::Value) -> String {
+    let resp = invoke(
+        LambdaHttpServer::new(|| {
+            App::new().route(
+                "/",
+                web::get()
+                    .to(|req: HttpRequest| format!("{:?}", req.extensions().get::<EventSource>())),
+            )
+        }),
+        event,
+    );
+    body_str(&resp)
+}
+
+#[test]
+fn event_source_rest() {
+    assert_eq!(event_source(rest_event("GET", "/")), "Some(ApiGatewayRest)");
+}
+
+#[test]
+fn event_source_http_v2() {
+    assert_eq!(
+        event_source(http_v2_event("GET", "/")),
+        "Some(ApiGatewayHttp)"
+    );
+}
+
+#[test]
+fn event_source_http_v1() {
+    // An HTTP API event with the payload format version 1.0
+    let mut event = rest_event("GET", "/");
+    event["version"] = json!("1.0");
+    assert_eq!(event_source(event), "Some(ApiGatewayHttp)");
+}
+
+#[test]
+fn event_source_alb() {
+    assert_eq!(event_source(alb_event("GET", "/")), "Some(Alb)");
+}
@@ -0,0 +1,325 @@
+//! Extractors and request extensions provided by the connector
+use actix_lambda_http::{
+    AuthorizerContext, BasePath, FormParams, LambdaHttpServer, LambdaPath, OriginalMethod,
+    RequestId,
+};
+use actix_web::{web, App, HttpRequest};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+mod common;
+use common::{alb_event, body_str, invoke, rest_event};
+
+#[derive(Deserialize)]
+struct UserPath {
+    id: u32,
+    tab: String,
+}
+
+/// Construct a REST API event for the resource `/users/{id}/{tab}`.
+fn user_event(id: &str, tab: &str) -> Value {
+    let path = format!("/users/{}/{}", id, tab);
+    let mut event = rest_event("GET", &path);
+    event["resource"] = json!("/users/{id}/{tab}");
+    event["requestContext"]["resourcePath"] = json!("/users/{id}/{tab}");
+    event["pathParameters"] = json!({ "id": id, "tab": tab });
+    event
+}
+
+/// Process `event` with an application that extracts `UserPath` from any
+/// path.
+fn extract_user_path(event: Value) -> Value {
+    invoke(
+        LambdaHttpServer::new(|| {
+            App::new().default_service(web::to(|path: LambdaPath<UserPath>| {
+                format!("{} {}", path.id, path.tab)
+            }))
+        }),
+        event,
+    )
+}
+
+#[test]
+fn lambda_path() {
+    let resp = extract_user_path(user_event("42", "posts"));
+    assert_eq!(resp["statusCode"], 200);
+    assert_eq!(body_str(&resp), "42 posts");
+}
+
+#[test]
+fn lambda_path_invalid() {
+    let resp = extract_user_path(user_event("forty-two", "posts"));
+    assert_eq!(resp["statusCode"], 404);
+}
+
+#[test]
+fn lambda_path_missing() {
+    let mut event = rest_event("GET", "/users/42");
+    event["pathParameters"] = json!(null);
+    let resp = extract_user_path(event);
+    assert_eq!(resp["statusCode"], 404);
+}
+
+/// Process `event` with `detect_base_path(true)` and return the `BasePath`
+/// seen by the application.
+fn detected_base_path(event: Value) -> String {
+    let resp = invoke(
+        LambdaHttpServer::new(|| {
+            App::new().default_service(web::to(|req: HttpRequest| {
+                format!("{:?} {}", req.extensions().get::<BasePath>(), req.path())
+            }))
+        })
+        .detect_base_path(true),
+        event,
+    );
+    body_str(&resp)
+}
+
+#[test]
+fn detect_base_path_default_endpoint() {
+    // The stage name is the prefix
+    assert_eq!(
+        detected_base_path(rest_event("GET", "/users/42")),
+        r#"Some(BasePath("/prod")) /users/42"#
+    );
+}
+
+#[test]
+fn detect_base_path_custom_domain() {
+    // A custom domain name with the base path mapping `v1`
+    let mut event = rest_event("GET", "/users/42");
+    event["headers"]["host"] = json!("api.example.com");
+    event["requestContext"]["path"] = json!("/v1/users/42");
+    assert_eq!(
+        detected_base_path(event),
+        r#"Some(BasePath("/v1")) /users/42"#
+    );
+
+    // A custom domain name with the empty base path mapping
+    let mut event = rest_event("GET", "/users/42");
+    event["headers"]["host"] = json!("api.example.com");
+    event["requestContext"]["path"] = json!("/users/42");
+    assert_eq!(detected_base_path(event), "None /users/42");
+}
+
+#[test]
+fn detect_base_path_disabled() {
+    let resp = invoke(
+        LambdaHttpServer::new(|| {
+            App::new().default_service(web::to(|req: HttpRequest| {
+                format!("{:?}", req.extensions().get::<BasePath>())
+            }))
+        }),
+        rest_event("GET", "/users/42"),
+    );
+    assert_eq!(body_str(&resp), "None");
+}
+
+/// Process a `{method}` request with `X-HTTP-Method-Override: {value}` and
+/// `honor_method_override(true)` and return the method and `OriginalMethod`
+/// seen by the application.
+fn overridden_method(method: &str, value: &str) -> String {
+    let mut event = alb_event(method, "/");
+    event["headers"]["x-http-method-override"] = json!(value);
+    let resp = invoke(
+        LambdaHttpServer::new(|| {
+            App::new().default_service(web::to(|req: HttpRequest| {
+                format!(
+                    "{} {:?}",
+                    req.method(),
+                    req.extensions().get::<OriginalMethod>()
+                )
+            }))
+        })
+        .honor_method_override(true),
+        event,
+    );
+    body_str(&resp)
+}
+
+#[test]
+fn method_override() {
+    assert_eq!(
+        overridden_method("POST", "DELETE"),
+        "DELETE Some(OriginalMethod(POST))"
+    );
+}
+
+#[test]
+fn method_override_ignored_on_get() {
+    assert_eq!(overridden_method("GET", "DELETE"), "GET None");
+}
+
+#[test]
+fn method_override_invalid() {
+    assert_eq!(overridden_method("POST", "DE LETE"), "POST None");
+}
+
+/// Post `body` to an application extracting `web::Json<UserPath>` with
+/// `json_error_bodies(json_error_bodies)`.
+fn post_json(body: &str, json_error_bodies: bool) -> Value {
+    let mut event = alb_event("POST", "/");
+    event["headers"]["content-type"] = json!("application/json");
+    event["body"] = json!(body);
+    invoke(
+        LambdaHttpServer::new(|| {
+            App::new().route(
+                "/",
+                web::post().to(|user: web::Json<UserPath>| format!("{} {}", user.id, user.tab)),
+            )
+        })
+        .json_error_bodies(json_error_bodies),
+        event,
+    )
+}
+
+#[test]
+fn json_error_bodies() {
+    let resp = post_json(r#"{"id": 42, "tab": "posts"}"#, true);
+    assert_eq!(resp["statusCode"], 200);
+    assert_eq!(body_str(&resp), "42 posts");
+
+    let resp = post_json(r#"{"id": "forty-two"}"#, true);
+    assert_eq!(resp["statusCode"], 400);
+    assert_eq!(resp["headers"]["content-type"], "application/json");
+    let error: Value = serde_json::from_str(&body_str(&resp)).unwrap();
+    let message = error["error"].as_str().unwrap();
+    assert!(message.starts_with("Json deserialize error"), "{}", message);
+}
+
+#[test]
+fn json_error_bodies_disabled() {
+    let resp = post_json(r#"{"id": "forty-two"}"#, false);
+    assert_eq!(resp["statusCode"], 400);
+    assert_ne!(resp["headers"]["content-type"], "application/json");
+}
+
+/// Process `event` and return the claims in the `AuthorizerContext` seen by
+/// the application.
+fn authorizer_claims(event: Value) -> String {
+    let resp = invoke(
+        LambdaHttpServer::new(|| {
+            App::new().default_service(web::to(|req: HttpRequest| {
+                match req.extensions().get::<AuthorizerContext>() {
+                    Some(context) => format!(
+                        "{} {} {}",
+                        context.0["principalId"],
+                        context.0["claims"]["sub"],
+                        context.0["claims"]["email"]
+                    ),
+                    None => "None".to_owned(),
+                }
+            }))
+        }),
+        event,
+    );
+    body_str(&resp)
+}
+
+#[test]
+fn authorizer_context() {
+    let mut event = rest_event("GET", "/");
+    event["requestContext"]["authorizer"] = json!({
+        "principalId": "user-42",
+        "claims": { "sub": "42", "email": "user@example.com" }
+    });
+    assert_eq!(
+        authorizer_claims(event),
+        r#""user-42" "42" "user@example.com""#
+    );
+}
+
+#[test]
+fn authorizer_context_missing() {
+    assert_eq!(authorizer_claims(rest_event("GET", "/")), "None");
+    assert_eq!(authorizer_claims(alb_event("GET", "/")), "None");
+}
+
+/// Post `body` with the content type `content_type` and
+/// `parse_form_body(true)` and return the `FormParams` and the body seen by
+/// the application.
+fn post_form(body: &str, content_type: &str) -> String {
+    let mut event = alb_event("POST", "/");
+    event["headers"]["content-type"] = json!(content_type);
+    event["body"] = json!(body);
+    let resp = invoke(
+        LambdaHttpServer::new(|| {
+            App::new().route(
+                "/",
+                web::post().to(|req: HttpRequest, body: String| {
+                    let extensions = req.extensions();
+                    let params = extensions.get::<FormParams>();
+                    format!(
+                        "{:?} {:?} {}",
+                        params.map(|params| &params.0),
+                        params.and_then(|params| params.get("name")),
+                        body
+                    )
+                }),
+            )
+        })
+        .parse_form_body(true),
+        event,
+    );
+    body_str(&resp)
+}
+
+#[test]
+fn form_params() {
+    assert_eq!(
+        post_form(
+            "name=J%C3%BCrgen+M&tag=a&tag=b",
+            "application/x-www-form-urlencoded; charset=utf-8"
+        ),
+        r#"Some([("name", "Jürgen M"), ("tag", "a"), ("tag", "b")]) Some("Jürgen M") name=J%C3%BCrgen+M&tag=a&tag=b"#
+    );
+}
+
+#[test]
+fn form_params_not_form() {
+    assert_eq!(post_form("name=x", "text/plain"), "None None name=x");
+}
+
+/// Process `event` and return the `RequestId` seen by the application.
+fn request_id(event: Value) -> String {
+    let resp = invoke(
+        LambdaHttpServer::new(|| {
+            App::new().default_service(web::to(|req: HttpRequest| {
+                format!("{:?}", req.extensions().get::<RequestId>())
+            }))
+        }),
+        event,
+    );
+    body_str(&resp)
+}
+
+#[test]
+fn request_id_api_gateway() {
+    assert_eq!(
+        request_id(rest_event("GET", "/")),
+        r#"Some(RequestId("c6af9ac6-7b61-11e6-9a41-93e8deadbeef"))"#
+    );
+}
+
+#[cfg(feature = "uuid")]
+#[test]
+fn request_id_generated() {
+    // `run_local` doesn't provide a Lambda request ID
+    let id = request_id(alb_event("GET", "/"));
+    let id = id
+        .trim_start_matches("Some(RequestId(\"")
+        .trim_end_matches("\"))");
+    assert!(uuid::Uuid::parse_str(id).is_ok(), "{}", id);
+
+    // A new one is generated for each request
+    assert_ne!(
+        request_id(alb_event("GET", "/")),
+        request_id(alb_event("GET", "/"))
+    );
+}
+
+#[cfg(not(feature = "uuid"))]
+#[test]
+fn request_id_missing() {
+    assert_eq!(request_id(alb_event("GET", "/")), "None");
+}
@@ -0,0 +1,170 @@
+//! The connector's log output
+use actix_lambda_http::LambdaHttpServer;
+use actix_web::{web, App, HttpResponse};
+use log::{Log, Metadata, Record};
+use serde_json::{json, Value};
+use std::{cell::RefCell, sync::Once};
+
+mod common;
+use common::{invoke, rest_event};
+
+/// Captures the connector's log messages emitted by the current thread.
+struct TestLogger;
+
+thread_local! {
+    static RECORDS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+impl Log for TestLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.target().starts_with("actix_lambda_http")
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            RECORDS.with(|x| x.borrow_mut().push(record.args().to_string()));
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Run `f` and return the log messages emitted by the connector.
+fn capture_logs(f: impl FnOnce()) -> String {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        log::set_logger(&TestLogger).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+    });
+
+    RECORDS.with(|x| x.borrow_mut().clear());
+    f();
+    RECORDS.with(|x| x.borrow().join("\n"))
+}
+
+#[test]
+fn redact_headers_and_query_parameters() {
+    let mut event = rest_event("GET", "/");
+    event["headers"]["authorization"] = json!("Bearer secret-bearer");
+    event["headers"]["cookie"] = json!("session=secret-session");
+    event["queryStringParameters"] = json!({ "token": "secret-token", "page": "2" });
+    event["multiValueQueryStringParameters"] = json!({ "token": ["secret-token"], "page": ["2"] });
+
+    let logs = capture_logs(|| {
+        invoke(
+            LambdaHttpServer::new(|| App::new().route("/", web::get().to(|| "ok"))),
+            event,
+        );
+    });
+
+    assert!(logs.contains("Reconstructed URI"), "{}", logs);
+    assert!(logs.contains("<redacted>"), "{}", logs);
+    assert!(logs.contains("page"), "{}", logs);
+    for secret in &["secret-bearer", "secret-session", "secret-token"] {
+        assert!(!logs.contains(secret), "{} found in {}", secret, logs);
+    }
+}
+
+#[test]
+fn redact_query_parameters_custom() {
+    let mut event = rest_event("GET", "/");
+    event["queryStringParameters"] = json!({ "Sig": "secret-sig", "token": "visible" });
+    event["multiValueQueryStringParameters"] =
+        json!({ "Sig": ["secret-sig"], "token": ["visible"] });
+
+    let logs = capture_logs(|| {
+        invoke(
+            LambdaHttpServer::new(|| App::new().route("/", web::get().to(|| "ok")))
+                .redact_query_parameters(vec!["sig"]),
+            event,
+        );
+    });
+
+    assert!(!logs.contains("secret-sig"), "{}", logs);
+    assert!(logs.contains("visible"), "{}", logs);
+}
+
+#[test]
+fn log_prefix_run_service() {
+    let logs = capture_logs(|| {
+        LambdaHttpServer::new(|| App::new().route("/", web::get().to(|| "ok")))
+            .log_prefix("my-function ")
+            .log_request_id(true)
+            .run_service(|service| {
+                let req = lambda_http::http::Request::builder()
+                    .uri("https://example.com/")
+                    .body(lambda_http::Body::Empty)
+                    .unwrap();
+                let ctx = lambda_runtime::Context {
+                    aws_request_id: "8476a536-e9f4-11e8-9739-2dfe598c3fcd".to_owned(),
+                    ..Default::default()
+                };
+                service.call_with_context(req, ctx).unwrap();
+            })
+            .unwrap();
+    });
+
+    assert!(!logs.is_empty());
+    for line in logs.lines() {
+        assert!(
+            line.starts_with("my-function [8476a536-e9f4-11e8-9739-2dfe598c3fcd] "),
+            "{}",
+            line
+        );
+    }
+}
+
+#[test]
+fn log_prefix_event() {
+    let logs = capture_logs(|| {
+        invoke(
+            LambdaHttpServer::new(|| App::new().route("/", web::get().to(|| "ok")))
+                .log_prefix("my-function: "),
+            rest_event("GET", "/"),
+        );
+    });
+
+    assert!(!logs.is_empty());
+    for line in logs.lines() {
+        assert!(line.starts_with("my-function: "), "{}", line);
+    }
+}
+
+#[test]
+fn no_content_with_body() {
+    let app = || {
+        App::new().route(
+            "/",
+            web::get().to(|| HttpResponse::NoContent().body("oops")),
+        )
+    };
+
+    let mut resp = Value::Null;
+    let logs = capture_logs(|| resp = invoke(LambdaHttpServer::new(app), rest_event("GET", "/")));
+    assert!(logs.contains("non-empty body"), "{}", logs);
+    assert_eq!(resp["statusCode"], 204);
+    assert_eq!(resp["body"], "oops");
+
+    let logs = capture_logs(|| {
+        resp = invoke(
+            LambdaHttpServer::new(app).normalize_invalid_responses(true),
+            rest_event("GET", "/"),
+        )
+    });
+    assert!(logs.contains("non-empty body"), "{}", logs);
+    assert_eq!(resp["statusCode"], 204);
+    assert!(resp["body"].as_str().unwrap_or("").is_empty(), "{}", resp);
+}
+
+#[test]
+fn no_content_without_body() {
+    let logs = capture_logs(|| {
+        invoke(
+            LambdaHttpServer::new(|| {
+                App::new().route("/", web::get().to(|| HttpResponse::NoContent().finish()))
+            }),
+            rest_event("GET", "/"),
+        );
+    });
+    assert!(!logs.contains("non-empty body"), "{}", logs);
+}
@@ -0,0 +1,81 @@
+//! Round trip of `multipart/form-data` request bodies
+use actix_lambda_http::LambdaHttpServer;
+use actix_multipart::{Field, Multipart};
+use actix_web::{web, App, Error, HttpResponse};
+use futures::{Future, Stream};
+use serde_json::json;
+
+mod common;
+use common::{body_str, invoke, rest_event};
+
+const BOUNDARY: &str = "----boundary7MA4YWxkTrZu0gW";
+
+/// Respond with the names of the fields and their contents (base64-encoded).
+fn upload(multipart: Multipart) -> impl Future<Item = HttpResponse, Error = Error> {
+    multipart
+        .and_then(|field: Field| {
+            let name = (field.content_disposition())
+                .and_then(|disposition| disposition.get_name().map(str::to_owned))
+                .unwrap_or_default();
+            field
+                .concat2()
+                .map(move |bytes| json!([name, base64::encode(&bytes)]))
+        })
+        .collect()
+        .map(|fields| HttpResponse::Ok().json(fields))
+        .map_err(Error::from)
+}
+
+/// Construct a `multipart/form-data` body with a text field and a file field.
+fn multipart_body(file: &[u8]) -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend_from_slice(
+        format!(
+            "--{0}\r\n\
+             Content-Disposition: form-data; name=\"title\"\r\n\
+             \r\n\
+             hello\r\n\
+             --{0}\r\n\
+             Content-Disposition: form-data; name=\"file\"; filename=\"data.bin\"\r\n\
+             Content-Type: application/octet-stream\r\n\
+             \r\n",
+            BOUNDARY
+        )
+        .as_bytes(),
+    );
+    body.extend_from_slice(file);
+    body.extend_from_slice(format!("\r\n--{}--\r\n", BOUNDARY).as_bytes());
+    body
+}
+
+#[test]
+fn binary_multipart_upload() {
+    // Every byte value, including CR and LF
+    let file: Vec<u8> = (0..=255).cycle().take(4096).collect();
+    let body = multipart_body(&file);
+
+    let mut event = rest_event("POST", "/upload");
+    event["headers"]["content-type"] = json!(format!("multipart/form-data; boundary={}", BOUNDARY));
+    event["headers"]["content-length"] = json!(body.len().to_string());
+    event["body"] = json!(base64::encode(&body));
+    event["isBase64Encoded"] = json!(true);
+
+    let resp = invoke(
+        LambdaHttpServer::new(|| {
+            App::new()
+                .wrap(actix_web::middleware::Logger::default())
+                .route("/upload", web::post().to_async(upload))
+        })
+        .binary_media_types(vec!["multipart/form-data"]),
+        event,
+    );
+    assert_eq!(resp["statusCode"], 200);
+
+    let fields: serde_json::Value = serde_json::from_str(&body_str(&resp)).unwrap();
+    assert_eq!(fields[0], json!(["title", base64::encode("hello")]));
+    assert_eq!(fields[1][0], "file");
+    assert_eq!(
+        base64::decode(fields[1][1].as_str().unwrap()).unwrap(),
+        file
+    );
+}
@@ -0,0 +1,1188 @@
+//! Reconstruction of requests
+use actix_lambda_http::{LambdaHttpServer, QueryArrayStyle, QueryEncoding, TrailingSlash};
+use actix_web::{
+    http::{self, uri::Scheme, Uri},
+    web, App, HttpRequest,
+};
+use lambda_http::RequestExt;
+use serde_json::{json, Value};
+use std::collections::{BTreeSet, HashMap};
+
+mod common;
+use common::{alb_event, body_str, call, http_v2_event, invoke, rest_event};
+
+/// Construct an application that responds with the `name` path parameter.
+macro_rules! files_app {
+    () => {
+        || {
+            App::new().route(
+                "/files/{name}",
+                web::get().to(|req: HttpRequest| req.match_info()["name"].to_owned()),
+            )
+        }
+    };
+}
+
+/// Construct an application that responds with the request URI for any
+/// path.
+macro_rules! uri_app {
+    () => {
+        || App::new().default_service(web::to(|req: HttpRequest| req.uri().to_string()))
+    };
+}
+
+/// Construct an application that responds with the request path (before
+/// percent-decoding) for any path.
+macro_rules! path_app {
+    () => {
+        || App::new().default_service(web::to(|req: HttpRequest| req.uri().path().to_owned()))
+    };
+}
+
+/// Construct a REST API event with the percent-decoded path `path` and the
+/// original path `raw_path`, which includes the stage name.
+fn rest_event_with_raw_path(path: &str, raw_path: &str) -> Value {
+    let mut event = rest_event("GET", path);
+    event["requestContext"]["path"] = json!(raw_path);
+    event
+}
+
+#[test]
+fn raw_path_encoded_slash() {
+    let event = rest_event_with_raw_path("/files/a/b", "/prod/files/a%2Fb");
+    let resp = invoke(LambdaHttpServer::new(files_app!()), event.clone());
+    assert_eq!(resp["statusCode"], 404);
+
+    let resp = invoke(
+        LambdaHttpServer::new(files_app!()).use_raw_path(true),
+        event,
+    );
+    assert_eq!(resp["statusCode"], 200);
+    assert_eq!(body_str(&resp), "a%2Fb");
+}
+
+#[test]
+fn raw_path_encoded_percent() {
+    let event = rest_event_with_raw_path("/files/100%", "/prod/files/100%25");
+    let resp = invoke(
+        LambdaHttpServer::new(files_app!()).use_raw_path(true),
+        event,
+    );
+    assert_eq!(body_str(&resp), "100%");
+}
+
+#[test]
+fn raw_path_plus() {
+    let event = rest_event_with_raw_path("/files/a+b", "/prod/files/a+b");
+    let resp = invoke(
+        LambdaHttpServer::new(files_app!()).use_raw_path(true),
+        event,
+    );
+    assert_eq!(body_str(&resp), "a+b");
+}
+
+#[test]
+fn raw_path_http_v2() {
+    let event = http_v2_event("GET", "/files/a%2Fb");
+    let resp = invoke(LambdaHttpServer::new(files_app!()), event.clone());
+    assert_eq!(resp["statusCode"], 404);
+
+    let resp = invoke(
+        LambdaHttpServer::new(files_app!()).use_raw_path(true),
+        event,
+    );
+    assert_eq!(body_str(&resp), "a%2Fb");
+}
+
+/// Send the query parameter `q=a b&c=d` with `encoding` and return the query
+/// string seen by the application and the parsed parameters.
+fn query_with_encoding(encoding: QueryEncoding) -> String {
+    let mut event = alb_event("GET", "/");
+    event["queryStringParameters"] = json!({ "q": "a b&c=d" });
+    let resp = invoke(
+        LambdaHttpServer::new(|| {
+            App::new().route(
+                "/",
+                web::get().to(
+                    |req: HttpRequest, query: web::Query<Vec<(String, String)>>| {
+                        format!("{} {:?}", req.query_string(), query.into_inner())
+                    },
+                ),
+            )
+        })
+        .query_encoding(encoding),
+        event,
+    );
+    body_str(&resp)
+}
+
+#[test]
+fn query_encoding_standard() {
+    // `&` and `=` are left as they are
+    assert_eq!(
+        query_with_encoding(QueryEncoding::default()),
+        r#"q=a%20b&c=d [("q", "a b"), ("c", "d")]"#
+    );
+}
+
+#[test]
+fn query_encoding_strict() {
+    assert_eq!(
+        query_with_encoding(QueryEncoding::Strict),
+        r#"q=a%20b%26c%3Dd [("q", "a b&c=d")]"#
+    );
+}
+
+#[test]
+fn query_encoding_full() {
+    assert_eq!(
+        query_with_encoding(QueryEncoding::Full),
+        r#"q=a%20b%26c%3Dd [("q", "a b&c=d")]"#
+    );
+}
+
+#[test]
+fn query_encoding_form() {
+    assert_eq!(
+        query_with_encoding(QueryEncoding::Form),
+        r#"q=a+b%26c%3Dd [("q", "a b&c=d")]"#
+    );
+}
+
+#[test]
+fn query_encoding_custom() {
+    let encoding = QueryEncoding::Custom(Box::new(|input| {
+        input
+            .replace(' ', "_")
+            .replace('&', "%26")
+            .replace('=', "%3D")
+    }));
+    assert_eq!(
+        query_with_encoding(encoding),
+        r#"q=a_b%26c%3Dd [("q", "a_b&c=d")]"#
+    );
+}
+
+/// Construct a request whose URI authority differs from its `Host` header.
+fn request_with_custom_host() -> lambda_http::Request {
+    lambda_http::http::Request::builder()
+        .uri("https://abcdef1234.execute-api.us-east-1.amazonaws.com/")
+        .header("host", "api.example.com")
+        .body(lambda_http::Body::Empty)
+        .unwrap()
+}
+
+#[test]
+fn host_header_authority_run_service() {
+    let resp = call(
+        LambdaHttpServer::new(uri_app!()),
+        request_with_custom_host(),
+    );
+    assert_eq!(
+        resp.body(),
+        &lambda_http::Body::from("https://api.example.com/")
+    );
+
+    let resp = call(
+        LambdaHttpServer::new(uri_app!()).host_header_authority(false),
+        request_with_custom_host(),
+    );
+    assert_eq!(
+        resp.body(),
+        &lambda_http::Body::from("https://abcdef1234.execute-api.us-east-1.amazonaws.com/")
+    );
+}
+
+#[test]
+fn map_lambda_request_query() {
+    let mut event = alb_event("GET", "/search");
+    event["queryStringParameters"] = json!({ "page": "1" });
+
+    // Rewrite a query parameter
+    let resp = invoke(
+        LambdaHttpServer::new(uri_app!()).map_lambda_request(|mut req, _| {
+            // `lambda_http` doesn't provide a way to replace the query
+            // parameters extracted from the event, so remove them and put the
+            // rewritten ones in the request URI
+            let context = req.request_context();
+            req.extensions_mut().clear();
+            req.extensions_mut().insert(context);
+            *req.uri_mut() = "/search?page=2".parse().unwrap();
+            req
+        }),
+        event.clone(),
+    );
+    assert_eq!(body_str(&resp), "https://example.com/search?page=2");
+
+    // Add a query parameter through the request URI
+    let resp = invoke(
+        LambdaHttpServer::new(uri_app!()).map_lambda_request(|mut req, _| {
+            *req.uri_mut() = "/search?canary=1".parse().unwrap();
+            req
+        }),
+        event,
+    );
+    assert_eq!(
+        body_str(&resp),
+        "https://example.com/search?canary=1&page=1"
+    );
+}
+
+#[test]
+fn host_header_authority_map_lambda_request() {
+    let set_host = |mut req: lambda_http::Request, _: &lambda_runtime::Context| {
+        (req.headers_mut()).insert("host", "api.example.com".parse().unwrap());
+        req
+    };
+
+    let resp = invoke(
+        LambdaHttpServer::new(uri_app!()).map_lambda_request(set_host),
+        rest_event("GET", "/"),
+    );
+    assert_eq!(body_str(&resp), "https://api.example.com/");
+
+    let resp = invoke(
+        LambdaHttpServer::new(uri_app!())
+            .map_lambda_request(set_host)
+            .host_header_authority(false),
+        rest_event("GET", "/"),
+    );
+    assert_eq!(
+        body_str(&resp),
+        "https://abcdef1234.execute-api.us-east-1.amazonaws.com/"
+    );
+}
+
+/// Construct an application that responds with the values of the headers
+/// `x-valid` and `x-invalid`.
+macro_rules! headers_app {
+    () => {
+        || {
+            App::new().route(
+                "/",
+                web::get().to(|req: HttpRequest| {
+                    let header = |name| {
+                        (req.headers().get(name))
+                            .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+                    };
+                    format!("{:?} {:?}", header("x-valid"), header("x-invalid"))
+                }),
+            )
+        }
+    };
+}
+
+/// Construct an event including the headers `x-valid: ok` and
+/// `x-invalid: {value}`.
+fn event_with_invalid_header(value: &str) -> Value {
+    let mut event = alb_event("GET", "/");
+    event["headers"]["x-valid"] = json!("ok");
+    event["headers"]["x-invalid"] = json!(value);
+    event
+}
+
+#[test]
+fn invalid_header_value_dropped() {
+    for &value in &["bell\u{7}", "new\nline", "nul\u{0}"] {
+        let resp = invoke(
+            LambdaHttpServer::new(headers_app!()),
+            event_with_invalid_header(value),
+        );
+        assert_eq!(resp["statusCode"], 200);
+        assert_eq!(body_str(&resp), r#"Some("ok") None"#);
+    }
+}
+
+#[test]
+fn invalid_header_value_encoded() {
+    for &(value, encoded) in &[("bell\u{7}", "bell%07"), ("new\nline", "new%0Aline")] {
+        let resp = invoke(
+            LambdaHttpServer::new(headers_app!()).encode_invalid_header_values(true),
+            event_with_invalid_header(value),
+        );
+        assert_eq!(resp["statusCode"], 200);
+        assert_eq!(body_str(&resp), format!("Some(\"ok\") Some({:?})", encoded));
+    }
+}
+
+#[test]
+fn invalid_header_name_dropped() {
+    let mut event = event_with_invalid_header("ok");
+    event["headers"]["x in valid"] = json!("1");
+    event["headers"]["x-non-string"] = json!(42);
+    for &encode in &[false, true] {
+        let resp = invoke(
+            LambdaHttpServer::new(headers_app!()).encode_invalid_header_values(encode),
+            event.clone(),
+        );
+        assert_eq!(resp["statusCode"], 200);
+        assert_eq!(body_str(&resp), r#"Some("ok") Some("ok")"#);
+    }
+}
+
+#[test]
+fn non_ascii_header_value_kept() {
+    // `HeaderValue` accepts `obs-text`
+    let resp = invoke(
+        LambdaHttpServer::new(headers_app!()),
+        event_with_invalid_header("\u{201c}smart quotes\u{201d}"),
+    );
+    assert_eq!(
+        body_str(&resp),
+        "Some(\"ok\") Some(\"\u{201c}smart quotes\u{201d}\")"
+    );
+}
+
+#[test]
+fn mount_base_path() {
+    let server = || LambdaHttpServer::new(uri_app!()).mount_base_path("/api/");
+
+    let resp = invoke(server(), alb_event("GET", "/api/users"));
+    assert_eq!(body_str(&resp), "https://example.com/users");
+
+    let resp = invoke(server(), alb_event("GET", "/api"));
+    assert_eq!(body_str(&resp), "https://example.com/");
+
+    // Only whole segments match
+    let resp = invoke(server(), alb_event("GET", "/apiary"));
+    assert_eq!(resp["statusCode"], 404);
+
+    let resp = invoke(server(), alb_event("GET", "/users"));
+    assert_eq!(resp["statusCode"], 404);
+}
+
+#[test]
+fn mount_base_path_pass_through() {
+    let resp = invoke(
+        LambdaHttpServer::new(uri_app!())
+            .mount_base_path("api")
+            .pass_through_unmounted_paths(true),
+        alb_event("GET", "/users"),
+    );
+    assert_eq!(resp["statusCode"], 200);
+    assert_eq!(body_str(&resp), "https://example.com/users");
+}
+
+#[test]
+fn alb_event_without_host() {
+    let mut event = alb_event("GET", "/health");
+    event["headers"].as_object_mut().unwrap().remove("host");
+
+    let resp = invoke(LambdaHttpServer::new(uri_app!()), event.clone());
+    assert_eq!(resp["statusCode"], 200);
+    assert_eq!(body_str(&resp), "https://localhost/health");
+
+    let resp = invoke(
+        LambdaHttpServer::new(|| {
+            App::new().default_service(web::to(|req: HttpRequest| {
+                format!("{} {:?}", req.uri(), req.headers().get("host"))
+            }))
+        })
+        .default_authority("internal.example.com"),
+        event,
+    );
+    assert_eq!(
+        body_str(&resp),
+        r#"https://internal.example.com/health Some("internal.example.com")"#
+    );
+}
+
+#[test]
+fn malformed_path() {
+    for &path in &["/a b", "/a\"b", "/a{b}"] {
+        let resp = invoke(LambdaHttpServer::new(uri_app!()), alb_event("GET", path));
+        assert_eq!(resp["statusCode"], 400, "{}", path);
+    }
+}
+
+#[test]
+fn path_with_newline() {
+    for event in &[
+        alb_event("GET", "/a\nb"),
+        alb_event("GET", "/a\r\nX-Injected: 1"),
+        rest_event("GET", "/a\nb"),
+        http_v2_event("GET", "/a\nb"),
+    ] {
+        let resp = invoke(LambdaHttpServer::new(uri_app!()), event.clone());
+        assert_eq!(resp["statusCode"], 400, "{}", event["path"]);
+    }
+}
+
+#[test]
+fn malformed_authority() {
+    for &host in &["exa mple.com", "[::1"] {
+        let mut event = alb_event("GET", "/");
+        event["headers"]["host"] = json!(host);
+        let resp = invoke(LambdaHttpServer::new(uri_app!()), event);
+        assert_eq!(resp["statusCode"], 400, "{}", host);
+    }
+}
+
+/// Process `event` with `strip_stage(true)` and return the request path.
+fn path_with_stripped_stage(event: Value) -> String {
+    let resp = invoke(
+        LambdaHttpServer::new(|| {
+            App::new().default_service(web::to(|req: HttpRequest| req.path().to_owned()))
+        })
+        .strip_stage(true),
+        event,
+    );
+    body_str(&resp)
+}
+
+#[test]
+fn strip_stage_prefix() {
+    assert_eq!(
+        path_with_stripped_stage(rest_event("GET", "/prod/users/42")),
+        "/users/42"
+    );
+    assert_eq!(path_with_stripped_stage(rest_event("GET", "/prod")), "/");
+}
+
+#[test]
+fn strip_stage_custom_domain() {
+    // A custom domain name with the base path mapping `v1`
+    let mut event = rest_event("GET", "/users/42");
+    event["headers"]["host"] = json!("api.example.com");
+    event["requestContext"]["path"] = json!("/v1/users/42");
+    assert_eq!(path_with_stripped_stage(event), "/users/42");
+
+    // Only a whole segment is removed
+    assert_eq!(
+        path_with_stripped_stage(rest_event("GET", "/production/users")),
+        "/production/users"
+    );
+}
+
+#[test]
+fn strip_stage_default() {
+    assert_eq!(
+        path_with_stripped_stage(http_v2_event("GET", "/users/42")),
+        "/users/42"
+    );
+    assert_eq!(
+        path_with_stripped_stage(http_v2_event("GET", "/$default/users")),
+        "/$default/users"
+    );
+}
+
+/// Process `event` with `uri_transform(transform)` and return the response.
+fn transform_uri(
+    transform: impl FnMut(Uri, &lambda_http::Request) -> Result<Uri, http::Error> + 'static,
+    event: Value,
+) -> Value {
+    invoke(
+        LambdaHttpServer::new(uri_app!()).uri_transform(transform),
+        event,
+    )
+}
+
+#[test]
+fn uri_transform_path() {
+    let resp = transform_uri(
+        |uri, _| {
+            let path = uri.path().replacen("/v2/", "/", 1);
+            let mut parts = uri.into_parts();
+            parts.path_and_query = Some(path.parse().unwrap());
+            Ok(Uri::from_parts(parts).unwrap())
+        },
+        alb_event("GET", "/v2/users"),
+    );
+    assert_eq!(body_str(&resp), "https://example.com/users");
+}
+
+#[test]
+fn uri_transform_query() {
+    let mut event = alb_event("GET", "/users");
+    event["queryStringParameters"] = json!({ "page": "2" });
+    let resp = transform_uri(
+        |uri, req| {
+            let mut parts = uri.into_parts();
+            let query = format!("{}&source={}", parts.path_and_query.unwrap(), req.method());
+            parts.path_and_query = Some(query.parse().unwrap());
+            Ok(Uri::from_parts(parts).unwrap())
+        },
+        event,
+    );
+    assert_eq!(
+        body_str(&resp),
+        "https://example.com/users?page=2&source=GET"
+    );
+}
+
+#[test]
+fn uri_transform_authority() {
+    let resp = transform_uri(
+        |uri, _| {
+            let mut parts = uri.into_parts();
+            parts.authority = Some("www.example.com".parse().unwrap());
+            Ok(Uri::from_parts(parts).unwrap())
+        },
+        alb_event("GET", "/"),
+    );
+    assert_eq!(body_str(&resp), "https://www.example.com/");
+}
+
+#[test]
+fn uri_transform_error() {
+    let resp = transform_uri(
+        |_, _| {
+            Err(lambda_http::http::Request::builder()
+                .uri("\n")
+                .body(())
+                .unwrap_err())
+        },
+        alb_event("GET", "/"),
+    );
+    assert_eq!(resp["statusCode"], 400);
+}
+
+/// Process a request routed through the API Gateway resource `/api/{proxy+}`
+/// with the `proxy` path parameter `proxy`, and return the request path and
+/// the `tail` path parameter seen by the application.
+fn path_from_proxy(proxy: &str) -> String {
+    let mut event = rest_event("GET", "/unrelated");
+    event["resource"] = json!("/api/{proxy+}");
+    event["requestContext"]["resourcePath"] = json!("/api/{proxy+}");
+    event["pathParameters"] = json!({ "proxy": proxy });
+    let resp = invoke(
+        LambdaHttpServer::new(|| {
+            App::new().route(
+                "/api/{tail:.*}",
+                web::get().to(|req: HttpRequest| {
+                    format!("{} {}", req.uri().path(), &req.match_info()["tail"])
+                }),
+            )
+        })
+        .path_from_proxy_parameter(true),
+        event,
+    );
+    body_str(&resp)
+}
+
+#[test]
+fn path_from_proxy_parameter_nested() {
+    assert_eq!(path_from_proxy("users"), "/api/users users");
+    assert_eq!(
+        path_from_proxy("users/42/posts"),
+        "/api/users/42/posts users/42/posts"
+    );
+}
+
+#[test]
+fn path_from_proxy_parameter_encoded() {
+    assert_eq!(path_from_proxy("files/a b"), "/api/files/a%20b files/a b");
+    assert_eq!(
+        path_from_proxy("files/café"),
+        "/api/files/caf%C3%A9 files/café"
+    );
+    assert_eq!(
+        path_from_proxy("files/a?b#c"),
+        "/api/files/a%3Fb%23c files/a?b#c"
+    );
+    assert_eq!(
+        path_from_proxy("files/100%"),
+        "/api/files/100%25 files/100%"
+    );
+    assert_eq!(path_from_proxy("files/%41"), "/api/files/%2541 files/%41");
+}
+
+#[test]
+fn merge_slashes() {
+    for &(path, merged) in &[
+        ("//users/42", "/users/42"),
+        ("/users/42//", "/users/42/"),
+        ("/users///42", "/users/42"),
+        ("//", "/"),
+        ("/users/42", "/users/42"),
+    ] {
+        let resp = invoke(
+            LambdaHttpServer::new(path_app!()).merge_slashes(true),
+            alb_event("GET", path),
+        );
+        assert_eq!(body_str(&resp), merged, "{}", path);
+
+        let resp = invoke(LambdaHttpServer::new(path_app!()), alb_event("GET", path));
+        assert_eq!(body_str(&resp), path);
+    }
+}
+
+#[test]
+fn merge_slashes_keeps_encoded_slashes() {
+    let event = rest_event_with_raw_path("/files//a//b", "/prod/files//a%2F%2Fb");
+    let resp = invoke(
+        LambdaHttpServer::new(path_app!())
+            .use_raw_path(true)
+            .merge_slashes(true),
+        event,
+    );
+    assert_eq!(body_str(&resp), "/files/a%2F%2Fb");
+}
+
+#[test]
+fn multibyte_path() {
+    for &name in &["café", "日本語", "🦀", "ß→∞"] {
+        let resp = invoke(
+            LambdaHttpServer::new(files_app!()),
+            rest_event("GET", &format!("/files/{}", name)),
+        );
+        assert_eq!(resp["statusCode"], 200, "{}", name);
+        assert_eq!(body_str(&resp), name);
+    }
+}
+
+#[test]
+fn multibyte_path_mixed_encoding() {
+    // ALB passes the path as it was received, which may or may not be
+    // percent-encoded
+    for &path in &["/files/caf%C3%A9", "/files/café"] {
+        let resp = invoke(LambdaHttpServer::new(files_app!()), alb_event("GET", path));
+        assert_eq!(body_str(&resp), "café", "{}", path);
+    }
+
+    let resp = invoke(
+        LambdaHttpServer::new(path_app!()),
+        alb_event("GET", "/%E6%97%A5/本"),
+    );
+    assert_eq!(body_str(&resp), "/%E6%97%A5/%E6%9C%AC");
+}
+
+#[test]
+fn multibyte_raw_path() {
+    let event = rest_event_with_raw_path("/files/日本/a/b", "/prod/files/日本/a%2Fb");
+    let resp = invoke(LambdaHttpServer::new(path_app!()).use_raw_path(true), event);
+    assert_eq!(body_str(&resp), "/files/%E6%97%A5%E6%9C%AC/a%2Fb");
+}
+
+/// Process an ALB event with `X-Forwarded-Proto: {proto}` (or without the
+/// header if `None`) and return the request URI.
+fn uri_with_forwarded_proto(proto: Option<&str>, default_scheme: Scheme) -> String {
+    let mut event = alb_event("GET", "/");
+    match proto {
+        Some(proto) => event["headers"]["x-forwarded-proto"] = json!(proto),
+        None => {
+            event["headers"]
+                .as_object_mut()
+                .unwrap()
+                .remove("x-forwarded-proto");
+        }
+    }
+    let resp = invoke(
+        LambdaHttpServer::new(uri_app!()).default_scheme(default_scheme),
+        event,
+    );
+    body_str(&resp)
+}
+
+#[test]
+fn forwarded_proto() {
+    assert_eq!(
+        uri_with_forwarded_proto(Some("http"), Scheme::HTTPS),
+        "http://example.com/"
+    );
+    assert_eq!(
+        uri_with_forwarded_proto(Some("https"), Scheme::HTTP),
+        "https://example.com/"
+    );
+}
+
+#[test]
+fn forwarded_proto_missing() {
+    assert_eq!(
+        uri_with_forwarded_proto(None, Scheme::HTTPS),
+        "https://example.com/"
+    );
+    assert_eq!(
+        uri_with_forwarded_proto(None, Scheme::HTTP),
+        "http://example.com/"
+    );
+}
+
+/// Process a REST API event for `/?q=é` with `max_uri_length(max)` and return
+/// the status code.
+fn status_with_max_uri_length(max: usize) -> Value {
+    let mut event = rest_event("GET", "/");
+    event["queryStringParameters"] = json!({ "q": "é" });
+    event["multiValueQueryStringParameters"] = json!({ "q": ["é"] });
+    let resp = invoke(LambdaHttpServer::new(uri_app!()).max_uri_length(max), event);
+    resp["statusCode"].clone()
+}
+
+#[test]
+fn max_uri_length() {
+    // `/?q=%C3%A9` is 10 bytes long (the decoded form `/?q=é` is 6 bytes)
+    assert_eq!(status_with_max_uri_length(10), 200);
+    assert_eq!(status_with_max_uri_length(9), 414);
+}
+
+/// Process an ALB event with `Host: {host}` and `allowed_hosts(allowed)` and
+/// return the status code.
+fn status_with_allowed_hosts(host: &str, allowed: &[&str]) -> Value {
+    let mut event = alb_event("GET", "/");
+    event["headers"]["host"] = json!(host);
+    let resp = invoke(
+        LambdaHttpServer::new(uri_app!()).allowed_hosts(allowed.to_vec()),
+        event,
+    );
+    resp["statusCode"].clone()
+}
+
+#[test]
+fn allowed_hosts_exact() {
+    let allowed = ["example.com"];
+    assert_eq!(status_with_allowed_hosts("example.com", &allowed), 200);
+    assert_eq!(status_with_allowed_hosts("EXAMPLE.com:443", &allowed), 200);
+    assert_eq!(status_with_allowed_hosts("www.example.com", &allowed), 421);
+}
+
+#[test]
+fn allowed_hosts_wildcard() {
+    let allowed = ["*.example.com"];
+    assert_eq!(status_with_allowed_hosts("api.example.com", &allowed), 200);
+    assert_eq!(status_with_allowed_hosts("a.b.example.com", &allowed), 200);
+    // The wildcard doesn't match the domain itself
+    assert_eq!(status_with_allowed_hosts("example.com", &allowed), 421);
+    assert_eq!(status_with_allowed_hosts("badexample.com", &allowed), 421);
+}
+
+#[test]
+fn allowed_hosts_mismatch() {
+    let allowed = ["example.com", "*.example.net"];
+    assert_eq!(
+        status_with_allowed_hosts("abcdef.execute-api.us-east-1.amazonaws.com", &allowed),
+        421
+    );
+}
+
+/// Process an ALB event for `path` with the query string `query` and
+/// `trailing_slash(mode)` and return the request URI.
+fn uri_with_trailing_slash(path: &str, query: Option<&str>, mode: TrailingSlash) -> String {
+    let mut event = alb_event("GET", path);
+    if let Some(query) = query {
+        event["queryStringParameters"] = json!({ "q": query });
+    }
+    let resp = invoke(
+        LambdaHttpServer::new(uri_app!()).trailing_slash(mode),
+        event,
+    );
+    body_str(&resp)
+}
+
+#[test]
+fn trailing_slash_keep() {
+    let uri = |path, query| uri_with_trailing_slash(path, query, TrailingSlash::Keep);
+    assert_eq!(uri("/a/", None), "https://example.com/a/");
+    assert_eq!(uri("/a", None), "https://example.com/a");
+    assert_eq!(uri("/a/", Some("1")), "https://example.com/a/?q=1");
+}
+
+#[test]
+fn trailing_slash_trim() {
+    let uri = |path, query| uri_with_trailing_slash(path, query, TrailingSlash::Trim);
+    assert_eq!(uri("/a/", None), "https://example.com/a");
+    assert_eq!(uri("/a//", None), "https://example.com/a");
+    assert_eq!(uri("/", None), "https://example.com/");
+    assert_eq!(uri("/a/", Some("1")), "https://example.com/a?q=1");
+    assert_eq!(uri("/", Some("1")), "https://example.com/?q=1");
+}
+
+#[test]
+fn trailing_slash_append() {
+    let uri = |path, query| uri_with_trailing_slash(path, query, TrailingSlash::Append);
+    assert_eq!(uri("/a", None), "https://example.com/a/");
+    assert_eq!(uri("/a/", None), "https://example.com/a/");
+    assert_eq!(uri("/a", Some("1")), "https://example.com/a/?q=1");
+}
+
+#[test]
+fn repeated_headers() {
+    let mut event = rest_event("GET", "/");
+    // API Gateway puts the last value in `headers`
+    event["headers"]["cookie"] = json!("b=2");
+    event["headers"]["accept-language"] = json!("ja");
+    event["multiValueHeaders"] = json!({
+        "host": ["abcdef1234.execute-api.us-east-1.amazonaws.com"],
+        "x-forwarded-proto": ["https"],
+        "cookie": ["a=1", "b=2"],
+        "accept-language": ["en", "ja"],
+    });
+    let resp = invoke(
+        LambdaHttpServer::new(|| {
+            App::new().default_service(web::to(|req: HttpRequest| {
+                // actix-http's `HeaderMap` doesn't preserve the order of
+                // repeated headers
+                let values = |name| {
+                    (req.headers().get_all(name))
+                        .map(|value| value.to_str().unwrap())
+                        .collect::<BTreeSet<_>>()
+                        .into_iter()
+                        .collect::<Vec<_>>()
+                        .join(" | ")
+                };
+                format!("{}\n{}", values("cookie"), values("accept-language"))
+            }))
+        }),
+        event,
+    );
+    assert_eq!(body_str(&resp), "a=1 | b=2\nen | ja");
+}
+
+/// Process a request for `uri` with the `Host` header `host` and return the
+/// request URI and the `Host` header seen by the application.
+///
+/// `host_header_authority` is disabled unless the URI has no authority.
+fn uri_and_host(uri: &str, host: Option<&str>, sync_host_header: bool) -> String {
+    let mut builder = lambda_http::http::Request::builder();
+    builder.uri(uri);
+    if let Some(host) = host {
+        builder.header("host", host);
+    }
+    let req = builder.body(lambda_http::Body::Empty).unwrap();
+    let resp = call(
+        LambdaHttpServer::new(|| {
+            App::new().default_service(web::to(|req: HttpRequest| {
+                format!("{} {:?}", req.uri(), req.headers().get("host"))
+            }))
+        })
+        .host_header_authority(uri.starts_with('/'))
+        .sync_host_header(sync_host_header),
+        req,
+    );
+    match resp.into_body() {
+        lambda_http::Body::Text(text) => text,
+        body => panic!("unexpected body: {:?}", body),
+    }
+}
+
+#[test]
+fn sync_host_header_mismatched() {
+    let uri = "https://abcdef1234.execute-api.us-east-1.amazonaws.com/";
+    assert_eq!(
+        uri_and_host(uri, Some("api.example.com"), true),
+        format!(
+            "{} Some(\"abcdef1234.execute-api.us-east-1.amazonaws.com\")",
+            uri
+        )
+    );
+    assert_eq!(
+        uri_and_host(uri, Some("api.example.com"), false),
+        format!("{} Some(\"api.example.com\")", uri)
+    );
+}
+
+#[test]
+fn sync_host_header_missing_header() {
+    assert_eq!(
+        uri_and_host("https://api.example.com/", None, true),
+        "https://api.example.com/ Some(\"api.example.com\")"
+    );
+    assert_eq!(
+        uri_and_host("https://api.example.com/", None, false),
+        "https://api.example.com/ None"
+    );
+}
+
+#[test]
+fn sync_host_header_missing_authority() {
+    assert_eq!(
+        uri_and_host("/", Some("api.example.com"), true),
+        "https://api.example.com/ Some(\"api.example.com\")"
+    );
+}
+
+#[test]
+fn query_without_parameters() {
+    // The request has no query string parameters, but its URI has a query
+    let req = lambda_http::http::Request::builder()
+        .uri("https://example.com/search?a=b")
+        .body(lambda_http::Body::Empty)
+        .unwrap();
+    let resp = call(LambdaHttpServer::new(uri_app!()), req);
+    assert_eq!(
+        resp.body(),
+        &lambda_http::Body::from("https://example.com/search?a=b")
+    );
+}
+
+/// Process `event` with `synthesize_forwarded_headers(true)` and return the
+/// `X-Forwarded-*` headers seen by the application.
+fn forwarded_headers(event: Value) -> String {
+    let resp = invoke(
+        LambdaHttpServer::new(|| {
+            App::new().default_service(web::to(|req: HttpRequest| {
+                let header = |name| req.headers().get(name).cloned();
+                format!(
+                    "{:?} {:?} {:?}",
+                    header("x-forwarded-for"),
+                    header("x-forwarded-proto"),
+                    header("x-forwarded-port")
+                )
+            }))
+        })
+        .synthesize_forwarded_headers(true),
+        event,
+    );
+    body_str(&resp)
+}
+
+#[test]
+fn synthesize_forwarded_headers() {
+    let mut event = rest_event("GET", "/");
+    event["headers"]
+        .as_object_mut()
+        .unwrap()
+        .remove("x-forwarded-proto");
+    assert_eq!(
+        forwarded_headers(event),
+        r#"Some("192.0.2.1") Some("https") Some("443")"#
+    );
+}
+
+#[test]
+fn synthesize_forwarded_headers_no_overwrite() {
+    let mut event = rest_event("GET", "/");
+    event["headers"]["x-forwarded-for"] = json!("203.0.113.7, 198.51.100.1");
+    event["headers"]["x-forwarded-proto"] = json!("http");
+    event["headers"]["x-forwarded-port"] = json!("8080");
+    assert_eq!(
+        forwarded_headers(event),
+        r#"Some("203.0.113.7, 198.51.100.1") Some("http") Some("8080")"#
+    );
+}
+
+/// Process a REST API event without `X-Forwarded-Proto` with
+/// `default_scheme(default_scheme)` and return the scheme reported by
+/// `ConnectionInfo`.
+fn connection_info_scheme(default_scheme: Scheme) -> String {
+    let mut event = rest_event("GET", "/");
+    event["headers"]
+        .as_object_mut()
+        .unwrap()
+        .remove("x-forwarded-proto");
+    let resp = invoke(
+        LambdaHttpServer::new(|| {
+            App::new().default_service(web::to(|req: HttpRequest| {
+                req.connection_info().scheme().to_owned()
+            }))
+        })
+        .default_scheme(default_scheme),
+        event,
+    );
+    body_str(&resp)
+}
+
+#[test]
+fn connection_info_scheme_https() {
+    assert_eq!(connection_info_scheme(Scheme::HTTPS), "https");
+}
+
+#[test]
+fn connection_info_scheme_http() {
+    assert_eq!(connection_info_scheme(Scheme::HTTP), "http");
+}
+
+/// Process `event` with `peer_addr_from_source_ip(enabled)` and return the
+/// peer address seen by the application.
+fn peer_addr(event: Value, enabled: bool) -> String {
+    let resp = invoke(
+        LambdaHttpServer::new(|| {
+            App::new().default_service(web::to(|req: HttpRequest| format!("{:?}", req.peer_addr())))
+        })
+        .peer_addr_from_source_ip(enabled),
+        event,
+    );
+    body_str(&resp)
+}
+
+#[test]
+fn peer_addr_from_source_ip() {
+    assert_eq!(peer_addr(rest_event("GET", "/"), true), "Some(192.0.2.1:0)");
+
+    // ALB appends the client's address to `X-Forwarded-For`
+    let mut event = alb_event("GET", "/");
+    event["headers"]["x-forwarded-for"] = json!("203.0.113.7, 2001:db8::1");
+    assert_eq!(peer_addr(event, true), "Some([2001:db8::1]:0)");
+
+    assert_eq!(peer_addr(rest_event("GET", "/"), false), "None");
+}
+
+/// Process `event` with `synthesize_rfc7239_forwarded(true)` and return the
+/// `Forwarded` header seen by the application.
+fn rfc7239_forwarded(event: Value) -> String {
+    let resp = invoke(
+        LambdaHttpServer::new(|| {
+            App::new().default_service(web::to(|req: HttpRequest| {
+                format!("{:?}", req.headers().get("forwarded"))
+            }))
+        })
+        .synthesize_rfc7239_forwarded(true),
+        event,
+    );
+    body_str(&resp)
+}
+
+#[test]
+fn synthesize_rfc7239_forwarded_ipv4() {
+    assert_eq!(
+        rfc7239_forwarded(rest_event("GET", "/")),
+        r#"Some("for=192.0.2.1;proto=https;host=abcdef1234.execute-api.us-east-1.amazonaws.com")"#
+    );
+}
+
+#[test]
+fn synthesize_rfc7239_forwarded_ipv6() {
+    let mut event = rest_event("GET", "/");
+    event["requestContext"]["identity"]["sourceIp"] = json!("2001:db8::1");
+    event["headers"]["host"] = json!("example.com:8443");
+    assert_eq!(
+        rfc7239_forwarded(event),
+        r#"Some("for=\"[2001:db8::1]\";proto=https;host=\"example.com:8443\"")"#
+    );
+}
+
+#[test]
+fn synthesize_rfc7239_forwarded_present() {
+    let mut event = rest_event("GET", "/");
+    event["headers"]["forwarded"] = json!("for=198.51.100.17;proto=http");
+    assert_eq!(
+        rfc7239_forwarded(event),
+        r#"Some("for=198.51.100.17;proto=http")"#
+    );
+}
+
+/// Send the query parameter `tag` with the values `a`, `b c`, and `d` with
+/// `style` and return the query string seen by the application.
+fn query_with_array_style(style: QueryArrayStyle) -> String {
+    let mut event = rest_event("GET", "/");
+    event["queryStringParameters"] = json!({ "tag": "d" });
+    event["multiValueQueryStringParameters"] = json!({ "tag": ["a", "b c", "d"] });
+    let resp = invoke(
+        LambdaHttpServer::new(|| {
+            App::new().default_service(web::to(|req: HttpRequest| req.query_string().to_owned()))
+        })
+        .query_array_style(style),
+        event,
+    );
+    body_str(&resp)
+}
+
+#[test]
+fn query_array_style_repeat() {
+    assert_eq!(
+        query_with_array_style(QueryArrayStyle::Repeat),
+        "tag=a&tag=b%20c&tag=d"
+    );
+}
+
+#[test]
+fn query_array_style_brackets() {
+    assert_eq!(
+        query_with_array_style(QueryArrayStyle::Brackets),
+        "tag[]=a&tag[]=b%20c&tag[]=d"
+    );
+}
+
+#[test]
+fn query_array_style_comma() {
+    assert_eq!(
+        query_with_array_style(QueryArrayStyle::Comma),
+        "tag=a,b%20c,d"
+    );
+}
+
+#[test]
+fn query_in_path() {
+    let mut event = alb_event("GET", "/search?x=1");
+    event["queryStringParameters"] = json!({ "y": "2" });
+    let resp = invoke(LambdaHttpServer::new(uri_app!()), event);
+    assert_eq!(body_str(&resp), "https://example.com/search?x=1&y=2");
+
+    // No parameters
+    let resp = invoke(
+        LambdaHttpServer::new(uri_app!()),
+        alb_event("GET", "/search?x=1"),
+    );
+    assert_eq!(body_str(&resp), "https://example.com/search?x=1");
+}
+
+#[test]
+fn fragment_in_path() {
+    let mut event = alb_event("GET", "/search#results");
+    event["queryStringParameters"] = json!({ "y": "2" });
+    let resp = invoke(LambdaHttpServer::new(uri_app!()), event);
+    assert_eq!(body_str(&resp), "https://example.com/search?y=2");
+}
+
+/// Process a request with the headers `x-a: 1`, `x-a: 2`, and `x-b: 3` (three
+/// values, 12 bytes in total) and return the status code.
+fn header_limit_status(max_headers: Option<usize>, max_header_bytes: Option<usize>) -> u16 {
+    let req = lambda_http::http::Request::builder()
+        .uri("https://example.com/")
+        .header("x-a", "1")
+        .header("x-a", "2")
+        .header("x-b", "3")
+        .body(lambda_http::Body::Empty)
+        .unwrap();
+    let mut server = LambdaHttpServer::new(|| App::new().default_service(web::to(|| "ok")));
+    if let Some(max) = max_headers {
+        server = server.max_headers(max);
+    }
+    if let Some(max) = max_header_bytes {
+        server = server.max_header_bytes(max);
+    }
+    call(server, req).status().as_u16()
+}
+
+#[test]
+fn max_headers() {
+    // Each value of `x-a` is counted
+    assert_eq!(header_limit_status(Some(3), None), 200);
+    assert_eq!(header_limit_status(Some(2), None), 431);
+}
+
+#[test]
+fn max_header_bytes() {
+    assert_eq!(header_limit_status(None, Some(12)), 200);
+    assert_eq!(header_limit_status(None, Some(11)), 431);
+}
+
+/// Send the query parameter `email=a+b@example.com` with `encoding` and return
+/// the value decoded by `web::Query` and the query string seen by the
+/// application.
+fn query_with_plus(encoding: QueryEncoding) -> String {
+    let mut event = alb_event("GET", "/");
+    event["queryStringParameters"] = json!({ "email": "a+b@example.com" });
+    let resp = invoke(
+        LambdaHttpServer::new(|| {
+            App::new().default_service(web::to(
+                |req: HttpRequest, query: web::Query<HashMap<String, String>>| {
+                    format!("{} {}", query["email"], req.query_string())
+                },
+            ))
+        })
+        .query_encoding(encoding),
+        event,
+    );
+    assert_eq!(resp["statusCode"], 200);
+    body_str(&resp)
+}
+
+#[test]
+fn query_value_with_plus() {
+    assert_eq!(
+        query_with_plus(QueryEncoding::Standard),
+        "a+b@example.com email=a%2Bb@example.com"
+    );
+    assert_eq!(
+        query_with_plus(QueryEncoding::Strict),
+        "a+b@example.com email=a%2Bb@example.com"
+    );
+    assert_eq!(
+        query_with_plus(QueryEncoding::Full),
+        "a+b@example.com email=a%2Bb%40example.com"
+    );
+    assert_eq!(
+        query_with_plus(QueryEncoding::Form),
+        "a+b@example.com email=a%2Bb%40example.com"
+    );
+}
@@ -0,0 +1,1132 @@
+//! Conversion of responses
+use actix_lambda_http::{CorsPreflight, InvalidMediaType, LambdaHttpServer, LambdaResponseHints};
+use actix_web::{
+    error::{ErrorBadGateway, ErrorNotFound},
+    http::{
+        header::{HttpDate, CONTENT_TYPE},
+        Cookie, HeaderName, HeaderValue, StatusCode,
+    },
+    web::{self, Bytes},
+    App, HttpRequest, HttpResponse,
+};
+use futures::{
+    future::{self, Either},
+    stream, Future, Stream,
+};
+use serde_json::{json, Value};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+mod common;
+use common::{alb_event, body, call, http_v2_event, invoke, rest_event};
+
+/// Get the elements of a JSON array of strings in ascending order.
+fn sorted(values: &Value) -> Vec<&str> {
+    let mut values: Vec<&str> = (values.as_array().unwrap().iter())
+        .map(|value| value.as_str().unwrap())
+        .collect();
+    values.sort();
+    values
+}
+
+fn set_two_cookies() -> HttpResponse {
+    HttpResponse::Ok()
+        .cookie(Cookie::new("session", "abc"))
+        .cookie(Cookie::new("csrf", "def"))
+        .finish()
+}
+
+#[test]
+fn set_cookie_rest() {
+    let resp = invoke(
+        LambdaHttpServer::new(|| App::new().route("/", web::get().to(set_two_cookies))),
+        rest_event("GET", "/"),
+    );
+    assert_eq!(
+        sorted(&resp["multiValueHeaders"]["set-cookie"]),
+        ["csrf=def", "session=abc"]
+    );
+}
+
+#[test]
+fn set_cookie_alb_multi_value_headers() {
+    let mut event = alb_event("GET", "/");
+    event["multiValueHeaders"] = json!({ "host": ["example.com"] });
+    let resp = invoke(
+        LambdaHttpServer::new(|| App::new().route("/", web::get().to(set_two_cookies))),
+        event,
+    );
+    assert_eq!(
+        sorted(&resp["multiValueHeaders"]["set-cookie"]),
+        ["csrf=def", "session=abc"]
+    );
+}
+
+#[test]
+fn set_cookie_alb_cookie_case_hack() {
+    // `"multiValueHeaders": null` is the same as its absence
+    let mut null_multi_value_headers = alb_event("GET", "/");
+    null_multi_value_headers["multiValueHeaders"] = json!(null);
+
+    for event in &[alb_event("GET", "/"), null_multi_value_headers] {
+        let resp = invoke(
+            LambdaHttpServer::new(|| {
+                App::new().route(
+                    "/",
+                    web::get().to(|| {
+                        HttpResponse::Ok()
+                            .cookie(Cookie::new("session", "abc"))
+                            .cookie(Cookie::new("csrf", "def"))
+                            .cookie(Cookie::new("theme", "dark"))
+                            .finish()
+                    }),
+                )
+            })
+            .alb_cookie_case_hack(true),
+            event.clone(),
+        );
+
+        let headers = resp["headers"].as_object().unwrap();
+        let (mut names, mut values): (Vec<&str>, Vec<&str>) = (headers.iter())
+            .filter(|(name, _)| name.eq_ignore_ascii_case("set-cookie"))
+            .map(|(name, value)| (name.as_str(), value.as_str().unwrap()))
+            .unzip();
+        names.sort();
+        names.dedup();
+        values.sort();
+        assert_eq!(names.len(), 3, "{:?}", headers);
+        assert_eq!(values, ["csrf=def", "session=abc", "theme=dark"]);
+    }
+}
+
+#[test]
+fn set_cookie_http_v2() {
+    // `"multiValueHeaders": null` is the same as its absence
+    let mut null_multi_value_headers = http_v2_event("GET", "/");
+    null_multi_value_headers["multiValueHeaders"] = json!(null);
+
+    for event in &[http_v2_event("GET", "/"), null_multi_value_headers] {
+        let resp = invoke(
+            LambdaHttpServer::new(|| {
+                App::new().route(
+                    "/",
+                    web::get().to(|| {
+                        HttpResponse::Ok()
+                            .cookie(Cookie::build("session", "abc").http_only(true).finish())
+                            .cookie(Cookie::build("csrf", "def").path("/").finish())
+                            .finish()
+                    }),
+                )
+            }),
+            event.clone(),
+        );
+        assert_eq!(
+            sorted(&resp["cookies"]),
+            ["csrf=def; Path=/", "session=abc; HttpOnly"]
+        );
+        assert!(resp["headers"].get("set-cookie").is_none());
+        assert!(resp["multiValueHeaders"].get("set-cookie").is_none());
+    }
+}
+
+fn two_vary_headers() -> HttpResponse {
+    HttpResponse::Ok()
+        .header("Vary", "Accept")
+        .header("Vary", "Origin")
+        .finish()
+}
+
+#[test]
+fn repeated_headers_http_v2() {
+    let resp = invoke(
+        LambdaHttpServer::new(|| App::new().route("/", web::get().to(two_vary_headers))),
+        http_v2_event("GET", "/"),
+    );
+    // actix-http doesn't preserve the order of repeated headers
+    let mut values: Vec<_> = resp["headers"]["vary"]
+        .as_str()
+        .unwrap()
+        .split(", ")
+        .collect();
+    values.sort();
+    assert_eq!(values, ["Accept", "Origin"]);
+}
+
+#[test]
+fn repeated_headers_rest() {
+    let resp = invoke(
+        LambdaHttpServer::new(|| App::new().route("/", web::get().to(two_vary_headers))),
+        rest_event("GET", "/"),
+    );
+    assert_eq!(
+        sorted(&resp["multiValueHeaders"]["vary"]),
+        ["Accept", "Origin"]
+    );
+}
+
+fn set_amzn_headers() -> HttpResponse {
+    HttpResponse::Ok()
+        .header("X-Amzn-Remapped-Authorization", "a")
+        .header("x-custom-internal", "b")
+        .header("x-request-id", "c")
+        .finish()
+}
+
+#[test]
+fn strip_response_header_prefix() {
+    let app = || App::new().route("/", web::get().to(set_amzn_headers));
+
+    let resp = invoke(LambdaHttpServer::new(app), rest_event("GET", "/"));
+    assert_eq!(resp["headers"]["x-amzn-remapped-authorization"], "a");
+
+    let resp = invoke(
+        LambdaHttpServer::new(app).strip_response_header_prefix(true),
+        rest_event("GET", "/"),
+    );
+    assert!(resp["headers"]
+        .get("x-amzn-remapped-authorization")
+        .is_none());
+    assert!(resp["multiValueHeaders"]
+        .get("x-amzn-remapped-authorization")
+        .is_none());
+    assert_eq!(resp["headers"]["x-custom-internal"], "b");
+    assert_eq!(resp["headers"]["x-request-id"], "c");
+
+    let resp = invoke(
+        LambdaHttpServer::new(app)
+            .strip_response_header_prefix(true)
+            .stripped_response_header_prefix("X-Custom-"),
+        rest_event("GET", "/"),
+    );
+    assert_eq!(resp["headers"]["x-amzn-remapped-authorization"], "a");
+    assert!(resp["headers"].get("x-custom-internal").is_none());
+}
+
+fn hinted(status_description: Option<&str>, binary: Option<bool>) -> HttpResponse {
+    let mut resp = HttpResponse::Ok().content_type("text/plain").body("hello");
+    resp.extensions_mut().insert(LambdaResponseHints {
+        status_description: status_description.map(str::to_owned),
+        binary,
+    });
+    resp
+}
+
+#[test]
+fn response_hints_alb() {
+    let resp = invoke(
+        LambdaHttpServer::new(|| {
+            App::new().route("/", web::get().to(|| hinted(Some("200 Fine"), Some(true))))
+        }),
+        alb_event("GET", "/"),
+    );
+    assert_eq!(resp["statusCode"], 200);
+    assert_eq!(resp["statusDescription"], "200 Fine");
+    assert_eq!(resp["isBase64Encoded"], true);
+    assert_eq!(body(&resp), b"hello");
+}
+
+#[test]
+fn response_hints_text_alb() {
+    // Overrides `binary_media_types`
+    let resp = invoke(
+        LambdaHttpServer::new(|| {
+            App::new().route("/", web::get().to(|| hinted(None, Some(false))))
+        })
+        .binary_media_types(vec!["text/plain"]),
+        alb_event("GET", "/"),
+    );
+    assert_eq!(resp["statusDescription"], "200 OK");
+    assert_eq!(resp["isBase64Encoded"], false);
+    assert_eq!(resp["body"], "hello");
+}
+
+#[test]
+fn response_hints_rest() {
+    // API Gateway rejects `statusDescription`
+    let resp = invoke(
+        LambdaHttpServer::new(|| {
+            App::new().route("/", web::get().to(|| hinted(Some("200 Fine"), None)))
+        }),
+        rest_event("GET", "/"),
+    );
+    assert!(resp.get("statusDescription").is_none());
+}
+
+#[test]
+fn default_status_description_alb() {
+    let status_description = |status: u16| {
+        let resp = invoke(
+            LambdaHttpServer::new(move || {
+                App::new().default_service(web::to(move || {
+                    HttpResponse::new(StatusCode::from_u16(status).unwrap())
+                }))
+            }),
+            alb_event("GET", "/"),
+        );
+        assert_eq!(resp["statusCode"], status);
+        resp["statusDescription"].clone()
+    };
+    assert_eq!(status_description(404), "404 Not Found");
+    assert_eq!(status_description(503), "503 Service Unavailable");
+    // No known reason phrase
+    assert_eq!(status_description(599), "599");
+}
+
+/// Process a request for `path` with an application mounted at `/api` that
+/// redirects to `location`, and return the resulting `Location`.
+fn rewritten_location(path: &str, location: &'static str) -> Value {
+    let resp = invoke(
+        LambdaHttpServer::new(move || {
+            App::new().default_service(web::to(move || {
+                HttpResponse::Found().header("location", location).finish()
+            }))
+        })
+        .mount_base_path("/api")
+        .pass_through_unmounted_paths(true)
+        .rewrite_redirect_locations(true),
+        alb_event("GET", path),
+    );
+    resp["headers"]["location"].clone()
+}
+
+#[test]
+fn rewrite_redirect_locations_exact_prefix() {
+    assert_eq!(rewritten_location("/api", "/login"), "/api/login");
+    assert_eq!(rewritten_location("/api/", "/"), "/api/");
+}
+
+#[test]
+fn rewrite_redirect_locations_prefix_and_path() {
+    assert_eq!(
+        rewritten_location("/api/users", "/users/42?tab=posts"),
+        "/api/users/42?tab=posts"
+    );
+}
+
+#[test]
+fn rewrite_redirect_locations_non_matching() {
+    // Not under the base path
+    assert_eq!(rewritten_location("/other", "/login"), "/login");
+    // Not an absolute path
+    assert_eq!(
+        rewritten_location("/api/users", "https://example.com/"),
+        "https://example.com/"
+    );
+    assert_eq!(
+        rewritten_location("/api/users", "//example.com/"),
+        "//example.com/"
+    );
+    assert_eq!(rewritten_location("/api/users", "42"), "42");
+}
+
+/// Send a request with `If-None-Match: {if_none_match}` to an application
+/// responding with `ETag: "abc"`, and return the status code and the body.
+fn conditional_get(method: &str, if_none_match: &str) -> (Value, String) {
+    let mut event = rest_event(method, "/");
+    event["headers"]["if-none-match"] = json!(if_none_match);
+    let resp = invoke(
+        LambdaHttpServer::new(|| {
+            App::new().default_service(web::to(|| {
+                HttpResponse::Ok().header("etag", "\"abc\"").body("content")
+            }))
+        })
+        .conditional_get(true),
+        event,
+    );
+    (
+        resp["statusCode"].clone(),
+        resp["body"].as_str().unwrap_or("").to_owned(),
+    )
+}
+
+#[test]
+fn conditional_get_matching() {
+    for &tag in &["\"abc\"", "W/\"abc\"", "\"xyz\", \"abc\"", "*"] {
+        assert_eq!(
+            conditional_get("GET", tag),
+            (json!(304), String::new()),
+            "{}",
+            tag
+        );
+    }
+    assert_eq!(conditional_get("HEAD", "\"abc\"").0, 304);
+}
+
+#[test]
+fn conditional_get_non_matching() {
+    for &tag in &["\"xyz\"", "\"ABC\"", "abc"] {
+        assert_eq!(
+            conditional_get("GET", tag),
+            (json!(200), "content".to_owned()),
+            "{}",
+            tag
+        );
+    }
+    // Only `GET` and `HEAD` are conditional
+    assert_eq!(conditional_get("POST", "\"abc\"").0, 200);
+}
+
+#[test]
+fn try_binary_media_types_valid() {
+    let server = LambdaHttpServer::new(|| {
+        App::new().route(
+            "/",
+            web::get().to(|| HttpResponse::Ok().content_type("image/png").body("PNG")),
+        )
+    })
+    .try_binary_media_types(vec!["image/png", "application/vnd.api+json"])
+    .unwrap();
+    let resp = invoke(server, rest_event("GET", "/"));
+    assert_eq!(resp["isBase64Encoded"], true);
+    assert_eq!(body(&resp), b"PNG");
+}
+
+#[test]
+fn try_binary_media_types_malformed() {
+    for &ty in &["png", "image/", "foo/bar", "image/png; charset"] {
+        let result = LambdaHttpServer::new(App::new)
+            .try_binary_media_types(vec!["image/gif", ty])
+            .map(|_| ());
+        assert_eq!(result, Err(InvalidMediaType(ty.to_owned())));
+    }
+}
+
+#[test]
+fn empty_binary_response() {
+    // An empty body isn't base64-encoded even if the content type is binary
+    let server = || {
+        LambdaHttpServer::new(|| {
+            App::new().route(
+                "/",
+                web::get().to(|| HttpResponse::Ok().content_type("image/png").finish()),
+            )
+        })
+        .binary_media_types(vec!["image/png"])
+    };
+
+    let req = lambda_http::http::Request::builder()
+        .uri("https://example.com/")
+        .body(lambda_http::Body::Empty)
+        .unwrap();
+    let resp = call(server(), req);
+    assert_eq!(resp.status(), 200);
+    assert_eq!(*resp.body(), lambda_http::Body::Empty);
+
+    let resp = invoke(server(), rest_event("GET", "/"));
+    assert_eq!(resp["statusCode"], 200);
+    assert_eq!(resp["isBase64Encoded"], false);
+    assert!(resp["body"].as_str().unwrap_or("").is_empty(), "{}", resp);
+}
+
+fn hop_by_hop_headers() -> HttpResponse {
+    HttpResponse::Ok()
+        .header("connection", "keep-alive, X-Internal")
+        .header("keep-alive", "timeout=5")
+        .header("upgrade", "h2c")
+        .header("x-internal", "1")
+        .header("x-public", "1")
+        .finish()
+}
+
+/// Get the sorted names of the headers in a response.
+fn header_names(resp: &Value) -> Vec<&str> {
+    let mut names: Vec<&str> = (resp["headers"].as_object().unwrap().keys())
+        .map(String::as_str)
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn hop_by_hop_headers_removed() {
+    let resp = invoke(
+        LambdaHttpServer::new(|| App::new().route("/", web::get().to(hop_by_hop_headers))),
+        rest_event("GET", "/"),
+    );
+    assert_eq!(header_names(&resp), ["date", "x-public"]);
+}
+
+#[test]
+fn hop_by_hop_headers_kept() {
+    let resp = invoke(
+        LambdaHttpServer::new(|| App::new().route("/", web::get().to(hop_by_hop_headers)))
+            .keep_hop_by_hop_headers(vec!["Upgrade", "x-internal"]),
+        rest_event("GET", "/"),
+    );
+    assert_eq!(
+        header_names(&resp),
+        ["date", "upgrade", "x-internal", "x-public"]
+    );
+}
+
+/// Construct an application that responds with the content type specified by
+/// the request path (e.g., `/image/png`).
+macro_rules! content_type_app {
+    () => {
+        || {
+            App::new().default_service(web::to(|req: HttpRequest| {
+                HttpResponse::Ok()
+                    .content_type(&req.path()[1..])
+                    .body("body")
+            }))
+        }
+    };
+}
+
+#[test]
+fn add_binary_media_type_fn() {
+    let is_binary = |content_type: &str| {
+        let resp = invoke(
+            LambdaHttpServer::new(content_type_app!())
+                .binary_media_types(vec!["image/png"])
+                .add_binary_media_type_fn(|ty| ty.starts_with("font/")),
+            alb_event("GET", &format!("/{}", content_type)),
+        );
+        assert_eq!(body(&resp), b"body");
+        resp["isBase64Encoded"].as_bool().unwrap()
+    };
+    assert!(is_binary("image/png"));
+    assert!(is_binary("font/woff2"));
+    assert!(!is_binary("text/plain"));
+}
+
+/// Check if a response with `content_type` is transmitted as binary with
+/// `binary_media_types_glob(patterns)`.
+fn is_binary_glob(patterns: &[&str], content_type: &str) -> bool {
+    let resp = invoke(
+        LambdaHttpServer::new(content_type_app!()).binary_media_types_glob(patterns),
+        alb_event("GET", &format!("/{}", content_type)),
+    );
+    assert_eq!(body(&resp), b"body");
+    resp["isBase64Encoded"].as_bool().unwrap()
+}
+
+#[test]
+fn binary_media_types_glob_any() {
+    assert!(is_binary_glob(&["*/*"], "text/plain"));
+    assert!(is_binary_glob(&["*/*"], "application/json"));
+}
+
+#[test]
+fn binary_media_types_glob_subtype() {
+    assert!(is_binary_glob(&["image/*"], "image/png"));
+    // Case is ignored
+    assert!(is_binary_glob(&["image/*"], "Image/SVG+xml"));
+    assert!(!is_binary_glob(&["image/*"], "text/plain"));
+}
+
+#[test]
+fn binary_media_types_glob_suffix() {
+    let patterns = ["application/*+protobuf"];
+    assert!(is_binary_glob(
+        &patterns,
+        "application/vnd.example+protobuf"
+    ));
+    assert!(!is_binary_glob(&patterns, "application/protobuf"));
+    assert!(!is_binary_glob(&patterns, "application/vnd.example+json"));
+    assert!(!is_binary_glob(&patterns, "text/vnd.example+protobuf"));
+}
+
+#[test]
+fn binary_media_types_glob_non_matching() {
+    let patterns = ["image/png", "font/*"];
+    assert!(is_binary_glob(&patterns, "image/png"));
+    assert!(!is_binary_glob(&patterns, "image/jpeg"));
+    assert!(!is_binary_glob(&patterns, "text/plain"));
+    assert!(!is_binary_glob(&[], "image/png"));
+}
+
+/// Process a `{method}` request with the additional headers `headers` by an
+/// application with `cors_preflight`.
+fn preflight(method: &str, headers: Value) -> Value {
+    let mut event = alb_event(method, "/");
+    for (name, value) in headers.as_object().unwrap() {
+        event["headers"][name] = value.clone();
+    }
+    invoke(
+        LambdaHttpServer::new(|| App::new().default_service(web::to(|| "app"))).cors_preflight(
+            CorsPreflight {
+                allowed_origins: vec!["https://example.org".to_owned()],
+                allowed_methods: vec!["PUT".to_owned(), "DELETE".to_owned()],
+                allowed_headers: vec!["*".to_owned()],
+                max_age: Some(600),
+            },
+        ),
+        event,
+    )
+}
+
+#[test]
+fn cors_preflight() {
+    let resp = preflight(
+        "OPTIONS",
+        json!({
+            "origin": "https://example.org",
+            "access-control-request-method": "PUT",
+            "access-control-request-headers": "x-custom"
+        }),
+    );
+    assert_eq!(resp["statusCode"], 204);
+    let headers = &resp["headers"];
+    assert_eq!(
+        headers["access-control-allow-origin"],
+        "https://example.org"
+    );
+    assert_eq!(headers["access-control-allow-methods"], "PUT, DELETE");
+    assert_eq!(headers["access-control-allow-headers"], "x-custom");
+    assert_eq!(headers["access-control-max-age"], "600");
+    assert_eq!(headers["vary"], "Origin");
+}
+
+#[test]
+fn cors_preflight_forbidden() {
+    let resp = preflight(
+        "OPTIONS",
+        json!({
+            "origin": "https://evil.example",
+            "access-control-request-method": "PUT"
+        }),
+    );
+    assert_eq!(resp["statusCode"], 403);
+
+    let resp = preflight(
+        "OPTIONS",
+        json!({
+            "origin": "https://example.org",
+            "access-control-request-method": "PATCH"
+        }),
+    );
+    assert_eq!(resp["statusCode"], 403);
+}
+
+#[test]
+fn cors_preflight_pass_through() {
+    // Not a preflight request
+    for resp in &[
+        preflight("OPTIONS", json!({ "origin": "https://example.org" })),
+        preflight(
+            "PUT",
+            json!({
+                "origin": "https://example.org",
+                "access-control-request-method": "PUT"
+            }),
+        ),
+    ] {
+        assert_eq!(resp["statusCode"], 200);
+        assert_eq!(resp["body"], "app");
+    }
+}
+
+#[test]
+fn default_content_type() {
+    let get = |path: &str| {
+        invoke(
+            LambdaHttpServer::new(|| {
+                App::new()
+                    .route(
+                        "/",
+                        web::get().to(|| HttpResponse::Ok().body(vec![0xffu8, 0x00])),
+                    )
+                    .route(
+                        "/text",
+                        web::get()
+                            .to(|| HttpResponse::Ok().content_type("text/plain").body("text")),
+                    )
+                    .route("/empty", web::get().to(|| HttpResponse::Ok().finish()))
+            })
+            .default_content_type(HeaderValue::from_static("application/octet-stream"))
+            .binary_media_types(vec!["application/octet-stream"]),
+            alb_event("GET", path),
+        )
+    };
+
+    let resp = get("/");
+    assert_eq!(resp["headers"]["content-type"], "application/octet-stream");
+    assert_eq!(resp["isBase64Encoded"], true);
+    assert_eq!(body(&resp), [0xff, 0x00]);
+
+    // The existing `Content-Type` is kept
+    let resp = get("/text");
+    assert_eq!(resp["headers"]["content-type"], "text/plain");
+    assert_eq!(resp["body"], "text");
+
+    // An empty body doesn't need `Content-Type`
+    let resp = get("/empty");
+    assert!(resp["headers"].get("content-type").is_none(), "{}", resp);
+}
+
+#[test]
+fn header_name_case() {
+    let resp = invoke(
+        LambdaHttpServer::new(|| {
+            App::new().route(
+                "/",
+                web::get().to(|| {
+                    HttpResponse::Ok()
+                        .header("x-request-id", "42")
+                        .header("x-other", "1")
+                        .finish()
+                }),
+            )
+        })
+        .header_name_case(vec!["X-Request-ID"]),
+        rest_event("GET", "/"),
+    );
+    for key in &["headers", "multiValueHeaders"] {
+        let headers = resp[key].as_object().unwrap();
+        assert!(headers.contains_key("X-Request-ID"), "{:?}", headers);
+        assert!(!headers.contains_key("x-request-id"), "{:?}", headers);
+        assert!(headers.contains_key("x-other"), "{:?}", headers);
+    }
+}
+
+#[test]
+fn response_header_filter() {
+    let resp = invoke(
+        LambdaHttpServer::new(|| {
+            App::new().route(
+                "/",
+                web::get().to(|| {
+                    HttpResponse::Ok()
+                        .header("server", "actix-web")
+                        .header("x-powered-by", "rust")
+                        .header("x-request-id", "42")
+                        .finish()
+                }),
+            )
+        })
+        .response_header_filter(|name, _| name != "x-powered-by"),
+        rest_event("GET", "/"),
+    );
+    for key in &["headers", "multiValueHeaders"] {
+        let headers = resp[key].as_object().unwrap();
+        assert!(!headers.contains_key("x-powered-by"), "{:?}", headers);
+        assert!(headers.contains_key("server"), "{:?}", headers);
+        assert!(headers.contains_key("x-request-id"), "{:?}", headers);
+    }
+}
+
+/// Process a request with `date_header(date_header)` and return the `Date`
+/// header of the response. If `app_date` is set, the application sets the
+/// header by itself.
+fn response_date(date_header: bool, app_date: Option<&'static str>) -> Option<String> {
+    let resp = invoke(
+        LambdaHttpServer::new(move || {
+            App::new().default_service(web::to(move || {
+                let mut resp = HttpResponse::Ok();
+                if let Some(date) = app_date {
+                    resp.header("date", date);
+                }
+                resp.finish()
+            }))
+        })
+        .date_header(date_header),
+        rest_event("GET", "/"),
+    );
+    resp["headers"]["date"].as_str().map(ToOwned::to_owned)
+}
+
+#[test]
+fn date_header() {
+    // The header is formatted by `HttpDate` as an IMF-fixdate
+    let fixed = UNIX_EPOCH + Duration::from_secs(784_111_777);
+    assert_eq!(
+        HttpDate::from(fixed).to_string(),
+        "Sun, 06 Nov 1994 08:49:37 GMT"
+    );
+
+    let before = SystemTime::now() - Duration::from_secs(1);
+    let date = response_date(true, None).unwrap();
+    let after = SystemTime::now() + Duration::from_secs(1);
+    assert_eq!(date.len(), 29, "{}", date);
+    assert!(date.ends_with(" GMT"), "{}", date);
+    let date = SystemTime::from(date.parse::<HttpDate>().unwrap());
+    assert!(before <= date && date <= after, "{:?}", date);
+}
+
+#[test]
+fn date_header_no_overwrite() {
+    assert_eq!(
+        response_date(true, Some("Sun, 06 Nov 1994 08:49:37 GMT")).as_deref(),
+        Some("Sun, 06 Nov 1994 08:49:37 GMT")
+    );
+}
+
+#[test]
+fn date_header_disabled() {
+    assert_eq!(response_date(false, None), None);
+}
+
+/// Construct a response with a body stream that stalls after the first
+/// chunk if `stall` is set.
+fn streaming_response(stall: bool) -> HttpResponse {
+    let head = stream::once::<_, actix_web::Error>(Ok(Bytes::from_static(b"partial")));
+    let tail = if stall {
+        Either::A(future::empty().into_stream())
+    } else {
+        Either::B(stream::empty())
+    };
+    HttpResponse::Ok()
+        .content_type("text/plain")
+        .streaming(head.chain(tail))
+}
+
+#[test]
+fn body_read_timeout() {
+    let get = |stall| {
+        invoke(
+            LambdaHttpServer::new(move || {
+                App::new().default_service(web::to(move || streaming_response(stall)))
+            })
+            .body_read_timeout(Duration::from_millis(100)),
+            rest_event("GET", "/"),
+        )
+    };
+
+    let resp = get(false);
+    assert_eq!(resp["statusCode"], 200);
+    assert_eq!(resp["body"], "partial");
+
+    let resp = get(true);
+    assert_eq!(resp["statusCode"], 500);
+    assert_ne!(resp["body"], "partial");
+}
+
+#[test]
+fn append_charset() {
+    let content_type = |ty: &str| {
+        let resp = invoke(
+            LambdaHttpServer::new(content_type_app!())
+                .append_charset(true)
+                .binary_media_types(vec!["image/png", "text/csv"]),
+            alb_event("GET", &format!("/{}", ty)),
+        );
+        resp["headers"]["content-type"].as_str().unwrap().to_owned()
+    };
+    // No charset
+    assert_eq!(content_type("text/html"), "text/html; charset=utf-8");
+    assert_eq!(
+        content_type("application/json"),
+        "application/json; charset=utf-8"
+    );
+    // Existing charset
+    assert_eq!(
+        content_type("text/plain;charset=iso-8859-1"),
+        "text/plain;charset=iso-8859-1"
+    );
+    // Binary
+    assert_eq!(content_type("image/png"), "image/png");
+    assert_eq!(content_type("text/csv"), "text/csv");
+    // Not textual
+    assert_eq!(content_type("application/xml"), "application/xml");
+}
+
+#[test]
+fn binary_by_accept() {
+    let get = |accept: Option<&str>| {
+        let mut event = rest_event("GET", "/");
+        if let Some(accept) = accept {
+            event["headers"]["accept"] = json!(accept);
+        }
+        let resp = invoke(
+            LambdaHttpServer::new(|| {
+                App::new().route(
+                    "/",
+                    web::get().to(|| HttpResponse::Ok().content_type("image/png").body("PNG")),
+                )
+            })
+            .binary_by_accept(vec!["image/*"]),
+            event,
+        );
+        assert_eq!(body(&resp), b"PNG");
+        resp["isBase64Encoded"].as_bool().unwrap()
+    };
+    assert!(get(Some("image/webp, */*")));
+    // Only the first media range is considered
+    assert!(!get(Some("text/html, image/png")));
+    assert!(!get(Some("text/html")));
+    assert!(!get(None));
+}
+
+/// Construct a response whose body stream fails after the first chunk.
+fn failing_stream_response() -> HttpResponse {
+    let body = stream::once(Ok(Bytes::from_static(b"partial")))
+        .chain(stream::once(Err(ErrorBadGateway("upstream failed"))));
+    HttpResponse::Ok()
+        .content_type("text/plain")
+        .streaming(body)
+}
+
+#[test]
+fn body_error_fallback() {
+    let resp = invoke(
+        LambdaHttpServer::new(|| App::new().route("/", web::get().to(failing_stream_response)))
+            .body_error_fallback(|e| {
+                HttpResponse::ServiceUnavailable().body(format!("fallback: {}", e))
+            }),
+        rest_event("GET", "/"),
+    );
+    assert_eq!(resp["statusCode"], 503);
+    assert_eq!(resp["body"], "fallback: upstream failed");
+
+    // The fallback isn't used for a successful body
+    let resp = invoke(
+        LambdaHttpServer::new(|| App::new().route("/", web::get().to(|| "ok")))
+            .body_error_fallback(|_| panic!("the fallback shouldn't be called")),
+        rest_event("GET", "/"),
+    );
+    assert_eq!(resp["statusCode"], 200);
+    assert_eq!(resp["body"], "ok");
+}
+
+#[test]
+fn body_error_fallback_default() {
+    let resp = invoke(
+        LambdaHttpServer::new(|| App::new().route("/", web::get().to(failing_stream_response))),
+        rest_event("GET", "/"),
+    );
+    // The error is converted to a response like a handler error
+    assert_eq!(resp["statusCode"], 502);
+    assert_ne!(resp["body"], "partial");
+}
+
+/// Process a request for `path` with `set_content_length(set_content_length)`
+/// and return the `Content-Length` header of the response.
+fn response_content_length(path: &str, set_content_length: bool) -> Option<String> {
+    let resp = invoke(
+        LambdaHttpServer::new(|| {
+            App::new()
+                .route(
+                    "/stale",
+                    web::get().to(|| {
+                        HttpResponse::Ok()
+                            .header("content-length", "100")
+                            .body("hello")
+                    }),
+                )
+                .route(
+                    "/absent",
+                    web::get().to(|| HttpResponse::Ok().body("hello")),
+                )
+                .route(
+                    "/head",
+                    web::get().to(|| HttpResponse::Ok().header("content-length", "42").finish()),
+                )
+        })
+        .set_content_length(set_content_length),
+        rest_event("GET", path),
+    );
+    resp["headers"]["content-length"]
+        .as_str()
+        .map(ToOwned::to_owned)
+}
+
+#[test]
+fn content_length_stale() {
+    for &set in &[false, true] {
+        assert_eq!(response_content_length("/stale", set).as_deref(), Some("5"));
+    }
+}
+
+#[test]
+fn content_length_absent() {
+    assert_eq!(response_content_length("/absent", false), None);
+    assert_eq!(
+        response_content_length("/absent", true).as_deref(),
+        Some("5")
+    );
+}
+
+#[test]
+fn content_length_empty_body() {
+    // Kept for responses to `HEAD` requests
+    assert_eq!(
+        response_content_length("/head", false).as_deref(),
+        Some("42")
+    );
+}
+
+#[test]
+fn map_response_binary_classification() {
+    let get = |path: &str| {
+        invoke(
+            LambdaHttpServer::new(content_type_app!())
+                .binary_media_types(vec!["image/png"])
+                .map_response(|resp| {
+                    // Swap the classification of `image/png` and `text/plain`
+                    let swapped = match resp.headers().get("content-type") {
+                        Some(ty) if ty == "image/png" => "text/plain",
+                        Some(ty) if ty == "text/plain" => "image/png",
+                        _ => return,
+                    };
+                    (resp.headers_mut()).insert(CONTENT_TYPE, HeaderValue::from_static(swapped));
+                }),
+            alb_event("GET", path),
+        )
+    };
+
+    let resp = get("/text/plain");
+    assert_eq!(resp["headers"]["content-type"], "image/png");
+    assert_eq!(resp["isBase64Encoded"], true);
+    assert_eq!(body(&resp), b"body");
+
+    let resp = get("/image/png");
+    assert_eq!(resp["headers"]["content-type"], "text/plain");
+    assert_eq!(resp["isBase64Encoded"], false);
+    assert_eq!(resp["body"], "body");
+}
+
+/// Process `event` with `default_response_headers` adding
+/// `X-Content-Type-Options` and `Server` and return the response.
+fn with_default_response_headers(event: Value) -> Value {
+    invoke(
+        LambdaHttpServer::new(|| {
+            App::new().route("/", web::get().to(|| "ok")).route(
+                "/server",
+                web::get().to(|| HttpResponse::Ok().header("server", "app").finish()),
+            )
+        })
+        .default_response_headers(vec![
+            (
+                HeaderName::from_static("x-content-type-options"),
+                HeaderValue::from_static("nosniff"),
+            ),
+            (
+                HeaderName::from_static("server"),
+                HeaderValue::from_static("lambda"),
+            ),
+        ])
+        .allowed_hosts(vec!["example.com"]),
+        event,
+    )
+}
+
+#[test]
+fn default_response_headers() {
+    let resp = with_default_response_headers(alb_event("GET", "/"));
+    assert_eq!(resp["statusCode"], 200);
+    assert_eq!(resp["headers"]["x-content-type-options"], "nosniff");
+    assert_eq!(resp["headers"]["server"], "lambda");
+}
+
+#[test]
+fn default_response_headers_no_overwrite() {
+    let resp = with_default_response_headers(alb_event("GET", "/server"));
+    assert_eq!(resp["headers"]["x-content-type-options"], "nosniff");
+    assert_eq!(resp["headers"]["server"], "app");
+    assert_eq!(resp["multiValueHeaders"]["server"], json!(["app"]));
+}
+
+#[test]
+fn default_response_headers_connector_error() {
+    // `421 Misdirected Request` generated by `allowed_hosts`
+    let mut event = alb_event("GET", "/");
+    event["headers"]["host"] = json!("example.net");
+    let resp = with_default_response_headers(event);
+    assert_eq!(resp["statusCode"], 421);
+    assert_eq!(resp["headers"]["x-content-type-options"], "nosniff");
+    assert_eq!(resp["headers"]["server"], "lambda");
+}
+
+/// Process `event` with `error_content_type` and `error_body_template` set.
+fn with_error_content_type(event: Value) -> Value {
+    invoke(
+        LambdaHttpServer::new(|| {
+            App::new()
+                .route(
+                    "/error",
+                    web::get().to(|| Err::<HttpResponse, _>(ErrorNotFound("missing"))),
+                )
+                .route("/ok", web::get().to(|| "ok"))
+        })
+        .error_content_type(HeaderValue::from_static("application/problem+json"))
+        .error_body_template(r#"{"status": {status}, "title": "{reason}"}"#)
+        .allowed_hosts(vec!["example.com"]),
+        event,
+    )
+}
+
+#[test]
+fn error_content_type() {
+    let resp = with_error_content_type(alb_event("GET", "/error"));
+    assert_eq!(resp["statusCode"], 404);
+    assert_eq!(resp["headers"]["content-type"], "application/problem+json");
+    assert_eq!(resp["body"], r#"{"status": 404, "title": "Not Found"}"#);
+
+    // An error response generated by the connector
+    let mut event = alb_event("GET", "/ok");
+    event["headers"]["host"] = json!("example.net");
+    let resp = with_error_content_type(event);
+    assert_eq!(resp["statusCode"], 421);
+    assert_eq!(resp["headers"]["content-type"], "application/problem+json");
+    assert_eq!(
+        resp["body"],
+        r#"{"status": 421, "title": "Misdirected Request"}"#
+    );
+}
+
+#[test]
+fn error_content_type_success() {
+    let resp = with_error_content_type(alb_event("GET", "/ok"));
+    assert_eq!(resp["statusCode"], 200);
+    assert_eq!(resp["headers"]["content-type"], "text/plain; charset=utf-8");
+    assert_eq!(resp["body"], "ok");
+}
+
+/// Process a request for `path` by an application responding with
+/// `application/json`, which is considered binary, with
+/// `utf8_binary_as_text(16)`.
+///
+/// `/text` and `/long` respond with valid UTF-8 payloads of 7 and 17 bytes,
+/// `/invalid` with an invalid UTF-8 payload, and `/hinted` with a valid UTF-8
+/// payload marked as binary by `LambdaResponseHints`.
+fn utf8_binary_response(path: &str) -> Value {
+    invoke(
+        LambdaHttpServer::new(|| {
+            App::new().default_service(web::to(|req: HttpRequest| {
+                let body: &'static [u8] = match req.path() {
+                    "/text" | "/hinted" => br#"{"a":1}"#,
+                    "/long" => br#"{"a":"0123456789"}"#,
+                    _ => b"\xff\xfe",
+                };
+                let mut resp = HttpResponse::Ok()
+                    .content_type("application/json")
+                    .body(body);
+                if req.path() == "/hinted" {
+                    resp.extensions_mut().insert(LambdaResponseHints {
+                        status_description: None,
+                        binary: Some(true),
+                    });
+                }
+                resp
+            }))
+        })
+        .binary_media_types(vec!["application/json"])
+        .utf8_binary_as_text(16),
+        alb_event("GET", path),
+    )
+}
+
+#[test]
+fn utf8_binary_as_text() {
+    let resp = utf8_binary_response("/text");
+    assert_eq!(resp["isBase64Encoded"], false);
+    assert_eq!(resp["body"], r#"{"a":1}"#);
+}
+
+#[test]
+fn utf8_binary_as_text_not_applied() {
+    // Too long
+    let resp = utf8_binary_response("/long");
+    assert_eq!(resp["isBase64Encoded"], true);
+    assert_eq!(body(&resp), br#"{"a":"0123456789"}"#);
+
+    // Not valid UTF-8
+    let resp = utf8_binary_response("/invalid");
+    assert_eq!(resp["isBase64Encoded"], true);
+    assert_eq!(body(&resp), b"\xff\xfe");
+
+    // Explicitly marked as binary
+    let resp = utf8_binary_response("/hinted");
+    assert_eq!(resp["isBase64Encoded"], true);
+    assert_eq!(body(&resp), br#"{"a":1}"#);
+}
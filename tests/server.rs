@@ -0,0 +1,205 @@
+//! Construction of the application service
+use actix_http::{Request, Response};
+use actix_lambda_http::{AppService, LambdaHttpServer};
+use actix_server_config::ServerConfig;
+use actix_service::{NewService, Service};
+use actix_web::{body::Body, http::StatusCode, web, App, Error};
+use futures::{
+    future::{self, FutureResult},
+    Async, Poll,
+};
+use serde_json::json;
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+};
+
+mod common;
+use common::{alb_event, body_str, invoke};
+
+#[test]
+fn new_accepts_fn_once_factory() {
+    // Not `Clone`, so the factory can only be called once
+    struct Pool(u32);
+
+    let pool = Pool(42);
+    let resp = invoke(
+        LambdaHttpServer::new(move || {
+            App::new().data(pool).route(
+                "/",
+                web::get().to(|pool: web::Data<Pool>| pool.0.to_string()),
+            )
+        }),
+        alb_event("GET", "/"),
+    );
+    assert_eq!(body_str(&resp), "42");
+}
+
+/// Build the application in a separate function (see `AppService`).
+fn app() -> App<impl AppService, Body> {
+    App::new().route("/", web::get().to(|| "ok"))
+}
+
+#[test]
+fn new_accepts_fn_returning_app() {
+    let resp = invoke(LambdaHttpServer::new(app), alb_event("GET", "/"));
+    assert_eq!(body_str(&resp), "ok");
+}
+
+#[test]
+fn new_with_retries_recovers_from_init_failure() {
+    let attempts = Rc::new(Cell::new(0));
+    let attempts2 = Rc::clone(&attempts);
+    let resp = invoke(
+        LambdaHttpServer::new_with_retries(
+            move || {
+                attempts2.set(attempts2.get() + 1);
+                let fail = attempts2.get() == 1;
+                App::new()
+                    .data_factory(move || if fail { Err(()) } else { Ok(42u32) })
+                    .route("/", web::get().to(|data: web::Data<u32>| data.to_string()))
+            },
+            1,
+        ),
+        alb_event("GET", "/"),
+    );
+    assert_eq!(attempts.get(), 2);
+    assert_eq!(body_str(&resp), "42");
+}
+
+#[test]
+fn new_with_retries_gives_up() {
+    let attempts = Rc::new(Cell::new(0));
+    let attempts2 = Rc::clone(&attempts);
+    let result = LambdaHttpServer::new_with_retries(
+        move || {
+            attempts2.set(attempts2.get() + 1);
+            App::new()
+                .data_factory(|| Err::<u32, _>(()))
+                .route("/", web::get().to(|| "unreachable"))
+        },
+        2,
+    )
+    .run_service(|_| panic!("the service shouldn't be constructed"));
+    assert!(result.is_err());
+    assert_eq!(attempts.get(), 3);
+}
+
+#[test]
+fn on_init_runs_once_before_first_invocation() {
+    let events = Rc::new(RefCell::new(Vec::new()));
+    let events2 = Rc::clone(&events);
+    let events3 = Rc::clone(&events);
+    LambdaHttpServer::new(move || {
+        App::new().default_service(web::to(move || {
+            events2.borrow_mut().push("request");
+            "ok"
+        }))
+    })
+    .on_init(move |_| events3.borrow_mut().push("init"))
+    .run_service(|service| {
+        for _ in 0..3 {
+            let req = lambda_http::http::Request::builder()
+                .uri("https://example.com/")
+                .body(lambda_http::Body::Empty)
+                .unwrap();
+            service.call_with_context(req, Default::default()).unwrap();
+        }
+    })
+    .unwrap();
+    assert_eq!(*events.borrow(), ["init", "request", "request", "request"]);
+}
+
+/// An `InitError` type without any trait implementations.
+struct InitError;
+
+/// A minimal application service responding with `hello`.
+struct Hello;
+
+impl Service for Hello {
+    type Request = Request;
+    type Response = Response;
+    type Error = Error;
+    type Future = FutureResult<Response, Error>;
+
+    fn poll_ready(&mut self) -> Poll<(), Error> {
+        Ok(Async::Ready(()))
+    }
+
+    fn call(&mut self, _: Request) -> Self::Future {
+        future::ok(Response::Ok().body("hello"))
+    }
+}
+
+/// Constructs `Hello`, or fails with `InitError` if `fail` is set.
+struct NewHello {
+    fail: bool,
+}
+
+impl NewService for NewHello {
+    type Config = ServerConfig;
+    type Request = Request;
+    type Response = Response;
+    type Error = Error;
+    type Service = Hello;
+    type InitError = InitError;
+    type Future = FutureResult<Hello, InitError>;
+
+    fn new_service(&self, _: &ServerConfig) -> Self::Future {
+        if self.fail {
+            future::err(InitError)
+        } else {
+            future::ok(Hello)
+        }
+    }
+}
+
+#[test]
+fn custom_new_service() {
+    let mut resp = None;
+    let result = LambdaHttpServer::new(|| NewHello { fail: false }).run_service(|service| {
+        let req = lambda_http::http::Request::builder()
+            .uri("https://example.com/")
+            .body(lambda_http::Body::Empty)
+            .unwrap();
+        resp = Some(service.call_with_context(req, Default::default()).unwrap());
+    });
+    assert!(result.is_ok());
+    assert_eq!(*resp.unwrap().body(), lambda_http::Body::from("hello"));
+
+    let result = LambdaHttpServer::new(|| NewHello { fail: true })
+        .run_service(|_| panic!("the service shouldn't be constructed"));
+    assert!(matches!(result, Err(InitError)));
+}
+
+#[test]
+fn health_check() {
+    let calls = Rc::new(Cell::new(0));
+    let get = |method: &str, path: &str, host: &str| {
+        let calls = Rc::clone(&calls);
+        let mut event = alb_event(method, path);
+        event["headers"]["host"] = json!(host);
+        invoke(
+            LambdaHttpServer::new(move || {
+                App::new().default_service(web::to(move || {
+                    calls.set(calls.get() + 1);
+                    "app"
+                }))
+            })
+            .health_check("/health", StatusCode::NO_CONTENT)
+            .allowed_hosts(vec!["example.com"]),
+            event,
+        )["statusCode"]
+            .clone()
+    };
+
+    assert_eq!(get("GET", "/health", "example.com"), 204);
+    assert_eq!(get("HEAD", "/health", "example.com"), 204);
+    // `allowed_hosts` is skipped
+    assert_eq!(get("GET", "/health", "10.0.0.1"), 204);
+    assert_eq!(calls.get(), 0);
+
+    assert_eq!(get("POST", "/health", "example.com"), 200);
+    assert_eq!(get("GET", "/health/x", "example.com"), 200);
+    assert_eq!(calls.get(), 2);
+}
@@ -0,0 +1,142 @@
+//! Propagation of the AWS X-Ray trace ID
+use actix_lambda_http::LambdaHttpServer;
+use actix_web::{web, App, HttpRequest, HttpResponse};
+use std::sync::Mutex;
+
+/// Serializes the tests modifying the `_X_AMZN_TRACE_ID` environment
+/// variable.
+static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+const CONTEXT_TRACE_ID: &str = "Root=1-5759e988-bd862e3fe1be46a994272793;Sampled=1";
+const ENV_TRACE_ID: &str = "Root=1-5759e988-bd862e3fe1be46a994272794;Sampled=0";
+const HEADER_TRACE_ID: &str = "Root=1-5759e988-bd862e3fe1be46a994272795;Sampled=1";
+
+/// Process a request with `propagate_trace_id(true)` and return the
+/// `X-Amzn-Trace-Id` header seen by the application.
+///
+/// `xray_trace_id` is the trace ID in the invocation context, and `header` is
+/// the request's `X-Amzn-Trace-Id` header.
+fn propagated_trace_id(xray_trace_id: Option<&str>, header: Option<&str>) -> String {
+    let mut builder = lambda_http::http::Request::builder();
+    builder.uri("https://example.com/");
+    if let Some(header) = header {
+        builder.header("x-amzn-trace-id", header);
+    }
+    let req = builder.body(lambda_http::Body::Empty).unwrap();
+    let ctx = lambda_runtime::Context {
+        xray_trace_id: xray_trace_id.map(ToOwned::to_owned),
+        ..Default::default()
+    };
+
+    let mut resp = None;
+    LambdaHttpServer::new(|| {
+        App::new().default_service(web::to(|req: HttpRequest| {
+            format!("{:?}", req.headers().get("x-amzn-trace-id"))
+        }))
+    })
+    .propagate_trace_id(true)
+    .run_service(|service| resp = Some(service.call_with_context(req, ctx).unwrap()))
+    .unwrap();
+
+    match resp.unwrap().into_body() {
+        lambda_http::Body::Text(text) => text,
+        body => panic!("unexpected body: {:?}", body),
+    }
+}
+
+#[test]
+fn propagate_trace_id() {
+    let _guard = ENV_LOCK.lock().unwrap();
+    std::env::set_var("_X_AMZN_TRACE_ID", ENV_TRACE_ID);
+
+    // The invocation context takes precedence over the environment variable
+    assert_eq!(
+        propagated_trace_id(Some(CONTEXT_TRACE_ID), None),
+        format!("Some({:?})", CONTEXT_TRACE_ID)
+    );
+
+    // The existing header is kept
+    assert_eq!(
+        propagated_trace_id(Some(CONTEXT_TRACE_ID), Some(HEADER_TRACE_ID)),
+        format!("Some({:?})", HEADER_TRACE_ID)
+    );
+
+    std::env::remove_var("_X_AMZN_TRACE_ID");
+}
+
+#[test]
+fn propagate_trace_id_env_fallback() {
+    let _guard = ENV_LOCK.lock().unwrap();
+
+    std::env::set_var("_X_AMZN_TRACE_ID", ENV_TRACE_ID);
+    assert_eq!(
+        propagated_trace_id(None, None),
+        format!("Some({:?})", ENV_TRACE_ID)
+    );
+
+    std::env::remove_var("_X_AMZN_TRACE_ID");
+    assert_eq!(propagated_trace_id(None, None), "None");
+}
+
+/// Process a request with `echo_trace_id(true)` and return the
+/// `X-Amzn-Trace-Id` header of the response.
+///
+/// `header` is the request's `X-Amzn-Trace-Id` header. If `app_header` is
+/// set, the application sets the response header by itself.
+fn echoed_trace_id(
+    header: Option<&str>,
+    propagate_trace_id: bool,
+    app_header: bool,
+) -> Option<String> {
+    let mut builder = lambda_http::http::Request::builder();
+    builder.uri("https://example.com/");
+    if let Some(header) = header {
+        builder.header("x-amzn-trace-id", header);
+    }
+    let req = builder.body(lambda_http::Body::Empty).unwrap();
+
+    let mut resp = None;
+    LambdaHttpServer::new(move || {
+        App::new().default_service(web::to(move || {
+            let mut resp = HttpResponse::Ok();
+            if app_header {
+                resp.header("x-amzn-trace-id", "app");
+            }
+            resp.finish()
+        }))
+    })
+    .propagate_trace_id(propagate_trace_id)
+    .echo_trace_id(true)
+    .run_service(|service| resp = Some(service.call_with_context(req, Default::default()).unwrap()))
+    .unwrap();
+
+    (resp.unwrap().headers().get("x-amzn-trace-id")).map(|value| value.to_str().unwrap().to_owned())
+}
+
+#[test]
+fn echo_trace_id() {
+    assert_eq!(
+        echoed_trace_id(Some(HEADER_TRACE_ID), false, false).as_deref(),
+        Some(HEADER_TRACE_ID)
+    );
+    // The application's header is kept
+    assert_eq!(
+        echoed_trace_id(Some(HEADER_TRACE_ID), false, true).as_deref(),
+        Some("app")
+    );
+}
+
+#[test]
+fn echo_trace_id_env_fallback() {
+    let _guard = ENV_LOCK.lock().unwrap();
+    std::env::set_var("_X_AMZN_TRACE_ID", ENV_TRACE_ID);
+
+    // The header added by `propagate_trace_id` is echoed
+    assert_eq!(
+        echoed_trace_id(None, true, false).as_deref(),
+        Some(ENV_TRACE_ID)
+    );
+    assert_eq!(echoed_trace_id(None, false, false), None);
+
+    std::env::remove_var("_X_AMZN_TRACE_ID");
+}